@@ -0,0 +1,282 @@
+use std::ops::Range;
+
+use celestia_types::{
+    nmt::{Namespace, NS_SIZE},
+    Blob,
+    Share,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use monotree::Hash;
+
+use crate::block::{Header, OutgoingBlock, SignedTxnISRPair};
+use crate::errors::EasyFraudError;
+use crate::inclusion::{leaf_hash, merkle_root, root_from_aunts};
+
+// version 0 namespace id blocks are posted under unless configured otherwise
+pub const DEFAULT_NAMESPACE: &[u8] = b"easyfraud";
+
+// blob data: a signed header, an offset table and the pairs. see docs/protocol.md
+pub const BLOB_VERSION: u8 = 5;
+// the part of the header the signature is over
+const SIGNED_HEADER: usize = 112;
+const BLOB_HEADER: usize = SIGNED_HEADER + 64;
+const OFFSET_SIZE: usize = 4;
+
+// what the sequencer's signature is over: the domain, the scheme version, the chain
+// id's length (u32 le) and the chain id, then the signed part of the header, so it
+// doesn't verify on another chain
+const SIGNING_DOMAIN: &[u8] = b"easyfraud/block";
+const SIGNING_SCHEME: u8 = 1;
+
+// celestia sparse shares, share version 0. every share starts with the namespace
+// and an info byte (share version << 1 | sequence start). the first share of a blob
+// follows that with the data length as a u32 be, the rest carry data right away.
+// the last share is zero padded.
+pub const SHARE_SIZE: usize = 512;
+const SHARE_VERSION: u8 = 0;
+const CONTINUATION_PREFIX: usize = NS_SIZE + 1;
+const FIRST_PREFIX: usize = CONTINUATION_PREFIX + 4;
+
+// the header at the start of a blob's data
+#[derive(Debug)]
+pub struct BlobHeader {
+    pub count: usize,
+    pub height: u64,
+    pub apphash: Hash,
+    pub fee_recipient: Option<[u8; 32]>,
+    // merkle root over the serialized pairs, in order
+    pub pairs_root: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl BlobHeader {
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![BLOB_VERSION, 0, 0, 0];
+        data.extend_from_slice(&(self.count as u32).to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&self.apphash);
+        data.extend_from_slice(&self.fee_recipient.unwrap_or([0; 32]));
+        data.extend_from_slice(&self.pairs_root);
+        data.extend_from_slice(&self.signature);
+        data
+    }
+
+    fn signing_message(&self, chain_id: &str) -> Vec<u8> {
+        let mut message = Vec::with_capacity(SIGNING_DOMAIN.len() + 5 + chain_id.len() + SIGNED_HEADER);
+        message.extend_from_slice(SIGNING_DOMAIN);
+        message.push(SIGNING_SCHEME);
+        message.extend_from_slice(&(chain_id.len() as u32).to_le_bytes());
+        message.extend_from_slice(chain_id.as_bytes());
+        message.extend_from_slice(&self.serialize()[..SIGNED_HEADER]);
+        message
+    }
+
+    pub fn verify(&self, chain_id: &str, sequencer: &VerifyingKey) -> Result<(), EasyFraudError> {
+        sequencer.verify(&self.signing_message(chain_id), &Signature::from_bytes(&self.signature))
+            .map_err(|_| EasyFraudError::InvalidBlockSignature)
+    }
+
+    // whether pair is the one at index under the pairs root, aunts are its merkle proof
+    pub fn commits_to(&self, index: usize, pair: &SignedTxnISRPair, aunts: &[[u8; 32]]) -> bool {
+        root_from_aunts(index, self.count, leaf_hash(&pair.serialize()), aunts) == Some(self.pairs_root)
+    }
+}
+
+// the blob data of the block at height, signed by the sequencer for chain_id
+pub fn encode_block(chain_id: &str, sequencer: &SigningKey, height: u64, block: &OutgoingBlock) -> Result<Vec<u8>, EasyFraudError> {
+    let apphash = block.header.apphash.ok_or(EasyFraudError::NullApphash)?;
+    let pairs = block.pairs.iter().map(SignedTxnISRPair::serialize).collect::<Vec<Vec<u8>>>();
+    let mut header = BlobHeader {
+        count: pairs.len(),
+        height,
+        apphash,
+        fee_recipient: block.header.fee_recipient,
+        pairs_root: merkle_root(&pairs),
+        signature: [0; 64],
+    };
+    header.signature = sequencer.sign(&header.signing_message(chain_id)).to_bytes();
+    let mut data = header.serialize();
+    let mut offset = BLOB_HEADER + (pairs.len() + 1) * OFFSET_SIZE;
+    data.extend_from_slice(&(offset as u32).to_le_bytes());
+    for pair in &pairs {
+        offset += pair.len();
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+    }
+    for pair in &pairs {
+        data.extend_from_slice(pair);
+    }
+    Ok(data)
+}
+
+fn decode_header(data: &[u8]) -> Result<BlobHeader, EasyFraudError> {
+    if data.len() < BLOB_HEADER {
+        return Err(EasyFraudError::TruncatedBlob { expected: BLOB_HEADER, actual: data.len() });
+    }
+    if data[0] != BLOB_VERSION {
+        return Err(EasyFraudError::UnsupportedBlobVersion(data[0]));
+    }
+    if data[1..4] != [0; 3] {
+        return Err(EasyFraudError::InvalidBlobHeader);
+    }
+    let fee_recipient: [u8; 32] = data[48..80].try_into().unwrap();
+    Ok(BlobHeader {
+        count: u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize,
+        height: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+        apphash: data[16..48].try_into().unwrap(),
+        fee_recipient: Some(fee_recipient).filter(|key| *key != [0; 32]),
+        pairs_root: data[80..SIGNED_HEADER].try_into().unwrap(),
+        signature: data[SIGNED_HEADER..BLOB_HEADER].try_into().unwrap(),
+    })
+}
+
+// (rollup height, block). the pairs have to add up to the header's pairs root, the
+// signature isn't looked at.
+pub fn decode_block(data: &[u8]) -> Result<(u64, OutgoingBlock), EasyFraudError> {
+    let header = decode_header(data)?;
+    let count = header.count;
+    let table_end = table_end(count);
+    if data.len() < table_end {
+        return Err(EasyFraudError::TruncatedBlob { expected: table_end, actual: data.len() });
+    }
+    let offsets = data[BLOB_HEADER..table_end].chunks_exact(OFFSET_SIZE)
+        .map(|offset| u32::from_le_bytes(offset.try_into().unwrap()) as usize)
+        .collect::<Vec<usize>>();
+    // the pairs have to follow the offsets and each other without gaps
+    if offsets[0] != table_end || offsets.windows(2).any(|w| w[1] < w[0]) {
+        return Err(EasyFraudError::InvalidBlobHeader);
+    }
+    let end = offsets[count];
+    if data.len() < end {
+        return Err(EasyFraudError::TruncatedBlob { expected: end, actual: data.len() });
+    }
+    if data.len() > end {
+        return Err(EasyFraudError::TrailingBlobData);
+    }
+    let pairs = offsets.windows(2)
+        .map(|w| SignedTxnISRPair::from_slice(&data[w[0]..w[1]]))
+        .collect::<Result<Vec<SignedTxnISRPair>, EasyFraudError>>()?;
+    if merkle_root(&pairs.iter().map(SignedTxnISRPair::serialize).collect::<Vec<Vec<u8>>>()) != header.pairs_root {
+        return Err(EasyFraudError::InvalidBlobHeader);
+    }
+    Ok((header.height, OutgoingBlock {
+        header: Header {
+            apphash: Some(header.apphash),
+            fee_recipient: header.fee_recipient,
+        },
+        pairs,
+    }))
+}
+
+// decode_block, for a block the sequencer signed for chain_id. anyone can post to a
+// namespace, nothing else is a block of the rollup's.
+pub fn decode_signed_block(data: &[u8], chain_id: &str, sequencer: &VerifyingKey) -> Result<(u64, OutgoingBlock), EasyFraudError> {
+    decode_header(data)?.verify(chain_id, sequencer)?;
+    decode_block(data)
+}
+
+pub fn block_to_blob(namespace: Namespace, chain_id: &str, sequencer: &SigningKey, height: u64, block: &OutgoingBlock) -> Result<Blob, EasyFraudError> {
+    Blob::new(namespace, encode_block(chain_id, sequencer, height, block)?)
+        .map_err(|e| EasyFraudError::Celestia(e.to_string()))
+}
+
+// the shares a blob takes up in the square, the same ones its commitment is over
+pub fn blob_shares(blob: &Blob) -> Vec<Share> {
+    let mut shares = vec![];
+    let mut data = &blob.data[..];
+    loop {
+        let first = shares.is_empty();
+        let mut buf = [0; SHARE_SIZE];
+        buf[..NS_SIZE].copy_from_slice(blob.namespace.as_bytes());
+        buf[NS_SIZE] = SHARE_VERSION << 1 | first as u8;
+        let prefix = if first {
+            buf[CONTINUATION_PREFIX..FIRST_PREFIX].copy_from_slice(&(blob.data.len() as u32).to_be_bytes());
+            FIRST_PREFIX
+        } else {
+            CONTINUATION_PREFIX
+        };
+        let len = data.len().min(SHARE_SIZE - prefix);
+        buf[prefix..prefix + len].copy_from_slice(&data[..len]);
+        data = &data[len..];
+        shares.push(Share {
+            data: buf
+        });
+        if data.is_empty() {
+            return shares;
+        }
+    }
+}
+
+// where the header is, always in the first share
+pub fn header_location() -> Vec<(usize, Range<usize>)> {
+    data_location(0..BLOB_HEADER)
+}
+
+// the header put back together from the share holding it, which has to be one a
+// blob starts with
+pub fn read_header<'a>(share: impl Fn(usize) -> Option<&'a [u8; SHARE_SIZE]>) -> Option<BlobHeader> {
+    if share(0)?[NS_SIZE] & 1 == 0 {
+        return None;
+    }
+    let bytes = read_data(header_location(), share)?;
+    decode_header(&bytes).ok()
+}
+
+// where the offsets the pair at index starts and ends at are, as (share, range inside
+// that share). anything that straddles a share boundary comes back in two pieces.
+pub fn offset_location(index: usize) -> Vec<(usize, Range<usize>)> {
+    let start = BLOB_HEADER + index * OFFSET_SIZE;
+    data_location(start..start + 2 * OFFSET_SIZE)
+}
+
+// where the offset table of a blob with count pairs ends, and its first pair starts
+fn table_end(count: usize) -> usize {
+    BLOB_HEADER + (count + 1) * OFFSET_SIZE
+}
+
+// where the bytes of the pair at index are, out of the count pairs the header says the
+// blob has. the offsets are read from the shares share hands out, None if it doesn't
+// have all of them, or they point back into the offset table or run backwards.
+pub fn pair_location<'a>(index: usize, count: usize, share: impl Fn(usize) -> Option<&'a [u8; SHARE_SIZE]>) -> Option<Vec<(usize, Range<usize>)>> {
+    if index >= count {
+        return None;
+    }
+    let offsets = read_data(offset_location(index), &share)?;
+    let start = u32::from_le_bytes(offsets[..OFFSET_SIZE].try_into().unwrap()) as usize;
+    let end = u32::from_le_bytes(offsets[OFFSET_SIZE..].try_into().unwrap()) as usize;
+    if start < table_end(count) || end < start {
+        return None;
+    }
+    Some(data_location(start..end))
+}
+
+// the pair at index put back together from the shares holding it and its offsets
+pub fn read_pair<'a>(index: usize, count: usize, share: impl Fn(usize) -> Option<&'a [u8; SHARE_SIZE]>) -> Option<SignedTxnISRPair> {
+    let bytes = read_data(pair_location(index, count, &share)?, &share)?;
+    SignedTxnISRPair::from_slice(&bytes).ok()
+}
+
+fn read_data<'a>(location: Vec<(usize, Range<usize>)>, share: impl Fn(usize) -> Option<&'a [u8; SHARE_SIZE]>) -> Option<Vec<u8>> {
+    Some(location.into_iter()
+        .map(|(i, range)| share(i).map(|data| &data[range]))
+        .collect::<Option<Vec<&[u8]>>>()?
+        .concat())
+}
+
+fn data_location(range: Range<usize>) -> Vec<(usize, Range<usize>)> {
+    let first_data = SHARE_SIZE - FIRST_PREFIX;
+    let continuation_data = SHARE_SIZE - CONTINUATION_PREFIX;
+    let mut pieces = vec![];
+    let mut start = range.start;
+    while start < range.end {
+        let (share, offset) = if start < first_data {
+            (0, FIRST_PREFIX + start)
+        } else {
+            let rest = start - first_data;
+            (1 + rest / continuation_data, CONTINUATION_PREFIX + rest % continuation_data)
+        };
+        let len = (range.end - start).min(SHARE_SIZE - offset);
+        pieces.push((share, offset..offset + len));
+        start += len;
+    }
+    pieces
+}
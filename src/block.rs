@@ -1,42 +1,36 @@
 use crate::{transaction::*, state::*, errors::EasyFraudError};
 use monotree::Hash;
-use celestia_types::{Share};
 pub struct IncomingBlock {
-    pub signed_transactions: Vec<[u8; 136]>,
+    // transaction envelopes, straight from the mempool
+    pub signed_transactions: Vec<Vec<u8>>,
 }
 
-#[derive(Debug)]
-pub struct SignedTxnISRPair(pub [u8; 136], pub Hash);
+// a transaction envelope and the ISR it leads to
+#[derive(Debug, Clone)]
+pub struct SignedTxnISRPair(pub Vec<u8>, pub Hash);
 impl SignedTxnISRPair {
-    pub fn serialize(&self) -> [u8; 168] {
-        let mut buf = [0; 168];
-        buf[..136].copy_from_slice(&self.0[..]);
-        buf[136..].copy_from_slice(&self.1[..]);
-        buf
-    }
-
-    pub fn deserialize(data: [u8; 168]) -> Result<Self, EasyFraudError> {
-        Ok(SignedTxnISRPair {
-            0: data[..136].try_into()
-                .map_err(|_| EasyFraudError::SerializePairsError)?,
-            1: data[136..].try_into()
-                .map_err(|_| EasyFraudError::SerializePairsError)?,
-        })
+    // envelope | isr 32
+    pub fn serialize(&self) -> Vec<u8> {
+        [&self.0[..], &self.1[..]].concat()
     }
 
+    // the envelope's length prefix says where the ISR starts, what's in
+    // the envelope isn't looked at until the transaction is run
     pub fn from_slice(data: &[u8]) -> Result<Self, EasyFraudError> {
-        Ok(SignedTxnISRPair {
-            0: data[..136].try_into()
-                .map_err(|_| EasyFraudError::SerializePairsError)?,
-            1: data[136..].try_into()
-                .map_err(|_| EasyFraudError::SerializePairsError)?,
-        })
+        let len = envelope_len(data)
+            .map_err(|_| EasyFraudError::DeserializePairsError)?;
+        if data.len() != len + 32 {
+            return Err(EasyFraudError::DeserializePairsError);
+        }
+        Ok(SignedTxnISRPair(data[..len].to_vec(), data[len..].try_into().unwrap()))
     }
 }
 
 #[derive(Debug)]
 pub struct Header {
     pub apphash: Option<Hash>,
+    // the proposer's account every fee in the block is paid into, None burns them
+    pub fee_recipient: Option<[u8; 32]>,
 }
 #[derive(Debug)]
 pub struct OutgoingBlock {
@@ -49,22 +43,27 @@ impl IncomingBlock {
         let mut outgoing_block = OutgoingBlock{
             header: Header{
                 apphash: state.root,
+                fee_recipient: state.fee_recipient,
             },
             pairs: vec![],
         };
 
-        // Deserialize, verify_and_run each signed_transaction, filter out the invalid ones, and add the valid ones to the outgoing block
-        self.signed_transactions.iter().for_each(|d| {
-            //let st = SignedTransaction::deserialize(*d).expect("couldn't deserialize");
-            // let txn = deserialize, skip if invalid:
-            let st = SignedTransaction::deserialize(*d);
-            if let Ok(stx) = st {
-                if let Ok(isr) = state.verify_and_run_transaction(&stx) {
-                    if let Some(isr) = isr {
-                        outgoing_block.pairs.push(SignedTxnISRPair(*d, isr))
+        // Deserialize every signed_transaction and check all their signatures up front, in batches.
+        // this only ever builds a proposal, see verify_signatures for why nothing else batches.
+        // the ones that decode and verify are run in order, and the valid ones added to the outgoing block
+        let txs = self.signed_transactions.iter()
+            .map(|d| Tx::decode(d).ok())
+            .collect::<Vec<Option<Tx>>>();
+        let signed = txs.iter().flatten().map(Tx::signed).collect::<Vec<&SignedTransaction>>();
+        let mut verified = verify_signatures(&state.chain_id, &signed).into_iter();
+        self.signed_transactions.iter().zip(&txs).for_each(|(d, tx)| {
+            // skip if invalid:
+            if let Some(tx) = tx {
+                if verified.next() == Some(true) {
+                    if let Ok(Some(isr)) = state.run_verified_transaction(tx) {
+                        outgoing_block.pairs.push(SignedTxnISRPair(d.clone(), isr))
                     }
                 }
-
             }
         });
         outgoing_block.header.apphash = state.root;
@@ -72,27 +71,6 @@ impl IncomingBlock {
     }
 }
 
-pub fn pairs_into_blob(pairs: Vec<SignedTxnISRPair>) -> Vec<Share> {
-    let mut result = vec![];
-    // take 3 SignedTxnISRPairs at a time, pack them into a [u8; 512]
-    for i in (0..pairs.len()).step_by(3) {
-        let mut buf = [0; 512];
-        buf[..168].copy_from_slice(&pairs[i].serialize()[..]);
-        if i + 1 >= pairs.len() {
-            break;
-        }
-        buf[168..336].copy_from_slice(&pairs[i+1].serialize()[..]);
-        if i + 2 >= pairs.len() {
-            break;
-        }
-        buf[336..504].copy_from_slice(&pairs[i+2].serialize()[..]);
-        result.push(Share {
-            data: buf
-        });
-    }
-    result
-}
-
 // still figuring out how i wanna do this...
 /*impl OutgoingBlock {
     pub fn process(&self, state: &mut State) -> Result<(), EasyFraudError> {
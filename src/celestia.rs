@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use celestia_types::{
+    nmt::Namespace,
+    Blob,
+    Commitment,
+};
+use serde_json::{json, Value};
+
+use crate::blob::blob_shares;
+use crate::da::DataAvailability;
+use crate::errors::EasyFraudError;
+use crate::http;
+use crate::inclusion::{DataRoots, ShareInclusion};
+use crate::utils::{from_base64, from_hex, to_base64};
+
+// blob.Submit waits for the blob to land in a block, give it a few of them
+const TIMEOUT: Duration = Duration::from_secs(60);
+// blob.Submit's gas price, -1 lets the node estimate one
+const GAS_PRICE: f64 = -1.0;
+
+// a celestia node's json-rpc api
+pub struct CelestiaDA {
+    // host:port and path of the rpc endpoint
+    address: String,
+    path: String,
+    // sent as a bearer token, celestia-node wants one unless it runs with --rpc.skip-auth
+    auth_token: Option<String>,
+}
+
+impl CelestiaDA {
+    pub fn new(url: &str, auth_token: Option<String>) -> Result<Self, EasyFraudError> {
+        let (address, path) = http::split_url(url)
+            .ok_or(EasyFraudError::InvalidArgument("--da".into()))?;
+        Ok(CelestiaDA {
+            address,
+            path,
+            auth_token,
+        })
+    }
+
+    // the result of a call, errors the node answers with come back as Celestia
+    fn call(&self, method: &str, params: Value) -> Result<Value, EasyFraudError> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }).to_string();
+        let rsp = http::post(&self.address, &self.path, "application/json", self.auth_token.as_deref(), body.as_bytes(), TIMEOUT)?;
+        let mut rsp: Value = serde_json::from_slice(&rsp)
+            .map_err(|e| EasyFraudError::Http(e.to_string()))?;
+        if let Some(error) = rsp.get("error") {
+            return Err(EasyFraudError::Celestia(error["message"].as_str()
+                .unwrap_or("unknown error")
+                .into()));
+        }
+        Ok(rsp["result"].take())
+    }
+
+    // the header at height, an error if the node hasn't got there yet
+    fn header(&self, height: u64) -> Result<Value, EasyFraudError> {
+        self.call("header.GetByHeight", json!([height]))
+            .map_err(|e| not_reached(e, height))
+    }
+
+    // every blob in namespace at height, along with where its first share is in the
+    // extended square, counting row by row
+    fn blobs(&self, height: u64, namespace: Namespace) -> Result<Vec<(Blob, Option<u64>)>, EasyFraudError> {
+        let result = match self.call("blob.GetAll", json!([height, [to_base64(namespace.as_bytes())]])) {
+            // an empty height looks like this, so does one a lagging node has no header
+            // for. only call it empty once the header is there.
+            Err(EasyFraudError::Celestia(message)) if message.contains("blob: not found") => {
+                self.header(height)?;
+                return Ok(vec![]);
+            }
+            result => result.map_err(|e| not_reached(e, height))?,
+        };
+        let blobs = match result {
+            Value::Null => return Ok(vec![]),
+            Value::Array(blobs) => blobs,
+            _ => return Err(EasyFraudError::Celestia("blob.GetAll did not return a list".into())),
+        };
+        blobs.iter()
+            .map(|blob| {
+                let field = |name: &str| blob[name].as_str()
+                    .ok_or(EasyFraudError::Celestia(format!("blob has no {}", name)))
+                    .and_then(from_base64);
+                if field("namespace")? != namespace.as_bytes() {
+                    return Err(EasyFraudError::ShareNamespaceMismatch);
+                }
+                // the commitment is worked out again instead of taken from the node
+                let rebuilt = Blob::new(namespace, field("data")?)
+                    .map_err(|e| EasyFraudError::Celestia(e.to_string()))?;
+                if field("commitment")? != rebuilt.commitment.0 {
+                    return Err(EasyFraudError::Celestia("blob does not match its commitment".into()));
+                }
+                Ok((rebuilt, blob["index"].as_u64()))
+            })
+            .collect()
+    }
+}
+
+fn not_reached(e: EasyFraudError, height: u64) -> EasyFraudError {
+    match e {
+        EasyFraudError::Celestia(message) if message.contains("from the future") => EasyFraudError::DAHeightNotReached(height),
+        e => e,
+    }
+}
+
+impl DataAvailability for CelestiaDA {
+    fn submit(&mut self, blobs: &[Blob]) -> Result<u64, EasyFraudError> {
+        let blobs = blobs.iter()
+            .map(|blob| json!({
+                "namespace": to_base64(blob.namespace.as_bytes()),
+                "data": to_base64(&blob.data),
+                "share_version": blob.share_version,
+                "commitment": to_base64(&blob.commitment.0),
+            }))
+            .collect::<Vec<Value>>();
+        self.call("blob.Submit", json!([blobs, GAS_PRICE]))?
+            .as_u64()
+            .ok_or(EasyFraudError::Celestia("blob.Submit did not return a height".into()))
+    }
+
+    fn get_all(&self, height: u64, namespace: Namespace) -> Result<Vec<Blob>, EasyFraudError> {
+        Ok(self.blobs(height, namespace)?.into_iter()
+            .map(|(blob, _)| blob)
+            .collect())
+    }
+
+    fn roots(&self, height: u64) -> Result<DataRoots, EasyFraudError> {
+        let header = self.header(height)?;
+        let roots = |name: &str| header["dah"][name].as_array()
+            .ok_or(EasyFraudError::Celestia(format!("header has no {}", name)))?
+            .iter()
+            .map(|root| root.as_str().ok_or(EasyFraudError::InvalidBase64).and_then(from_base64))
+            .collect::<Result<Vec<Vec<u8>>, EasyFraudError>>();
+        let roots = DataRoots {
+            row_roots: roots("row_roots")?,
+            column_roots: roots("column_roots")?,
+        };
+        // the data hash is what the validators signed, the roots have to add up to it
+        let data_hash = header["header"]["data_hash"].as_str()
+            .ok_or(EasyFraudError::Celestia("header has no data_hash".into()))
+            .and_then(from_hex)?;
+        if data_hash != roots.data_root() || roots.row_roots.len() != roots.column_roots.len() {
+            return Err(EasyFraudError::Celestia("header roots do not match its data hash".into()));
+        }
+        Ok(roots)
+    }
+
+    // blob.GetProof has an nmt proof for every row the blob is in, the one for the row
+    // share_index is in goes into the inclusion along with the share's row root and
+    // its way up to the data root. it's checked before it's handed out.
+    fn get_proof(&self, height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError> {
+        let params = json!([height, to_base64(namespace.as_bytes()), to_base64(&commitment.0)]);
+        let proofs = match self.call("blob.GetProof", params) {
+            Err(EasyFraudError::Celestia(message)) if message.contains("blob: not found") => {
+                return Err(EasyFraudError::BlobNotFound(height));
+            }
+            result => result?,
+        };
+        let (blob, index) = self.blobs(height, namespace)?.into_iter()
+            .find(|(blob, _)| blob.commitment == *commitment)
+            .ok_or(EasyFraudError::BlobNotFound(height))?;
+        let index = index.ok_or(EasyFraudError::Celestia("blob has no index".into()))? as usize;
+        let roots = self.roots(height)?;
+        let shares = blob_shares(&blob);
+        if share_index >= shares.len() {
+            return Err(EasyFraudError::InvalidInclusion);
+        }
+
+        // the index is into the extended square, the blob is in its original quarter
+        let width = roots.row_roots.len() / 2;
+        if width == 0 || index % (2 * width) >= width {
+            return Err(EasyFraudError::InvalidInclusion);
+        }
+        let first = index / (2 * width) * width + index % (2 * width);
+        let row = (first + share_index) / width;
+        let run = first.max(row * width)..(first + shares.len()).min((row + 1) * width);
+        let proof = &proofs[row - first / width];
+        let node_range = (proof["start"].as_u64(), proof["end"].as_u64());
+        if node_range != (Some((run.start - row * width) as u64), Some((run.end - row * width) as u64)) {
+            return Err(EasyFraudError::Celestia("blob.GetProof does not cover the blob's shares".into()));
+        }
+        let nmt_siblings = proof["nodes"].as_array()
+            .ok_or(EasyFraudError::Celestia("blob.GetProof has no nodes".into()))?
+            .iter()
+            .map(|node| node.as_str().ok_or(EasyFraudError::InvalidBase64).and_then(from_base64))
+            .collect::<Result<Vec<Vec<u8>>, EasyFraudError>>()?;
+
+        let inclusion = ShareInclusion {
+            square_width: width as u32,
+            row: row as u32,
+            column: (run.start - row * width) as u32,
+            shares: shares[run.start - first..run.end - first].iter().map(|share| share.data).collect(),
+            nmt_siblings,
+            row_root: roots.row_roots.get(row).ok_or(EasyFraudError::InvalidInclusion)?.clone(),
+            aunts: roots.row_aunts(row),
+        };
+        inclusion.verify(namespace, &roots.data_root())?;
+        Ok(inclusion)
+    }
+}
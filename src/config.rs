@@ -0,0 +1,128 @@
+use celestia_types::nmt::Namespace;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::blob::DEFAULT_NAMESPACE;
+use crate::errors::EasyFraudError;
+use crate::utils::from_hex;
+
+pub struct Config {
+    // tcp://host:port or unix:///path/to/socket
+    pub address: String,
+    pub chain_id: String,
+    // blocks CometBFT should keep around, 0 keeps everything
+    pub retain_blocks: u64,
+    // where genesis and committed blocks are journaled
+    pub home: String,
+    // take a state sync snapshot every n heights, 0 disables them
+    pub snapshot_interval: u64,
+    pub snapshot_keep_recent: usize,
+    // celestia namespace blocks are posted to, given as the hex of a version 0 id
+    pub namespace: Namespace,
+    // where committed blocks are published, see da::open. None doesn't publish them
+    pub da: Option<String>,
+    // bearer token for a celestia node, see `celestia <type> auth write`
+    pub da_auth_token: Option<String>,
+    // file holding the hex of the ed25519 seed published blocks are signed with
+    pub sequencer_key: Option<String>,
+    // the sequencer's public key, a derivation only takes blocks it signed
+    pub sequencer: Option<VerifyingKey>,
+    // rebuild state from the da layer instead of serving CometBFT
+    pub derive: bool,
+    // app state bytes InitChain would get, for a derivation starting from scratch
+    pub genesis: Option<String>,
+    // the genesis validators, they're part of the genesis app hash. a node that went
+    // through InitChain keeps them in <home>/validators, in the same format.
+    pub validators: Option<String>,
+    // da height the rollup's first block can be at, where a new derivation starts reading
+    pub da_start_height: u64,
+    // like derive, but report fraud to this sink, see watchtower::Sink::open
+    pub watch: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "tcp://127.0.0.1:26658".into(),
+            chain_id: "easyfraud".into(),
+            retain_blocks: 0,
+            home: ".easyfraud".into(),
+            snapshot_interval: 0,
+            snapshot_keep_recent: 2,
+            namespace: Namespace::new(0, DEFAULT_NAMESPACE).unwrap(),
+            da: None,
+            da_auth_token: None,
+            sequencer_key: None,
+            sequencer: None,
+            derive: false,
+            genesis: None,
+            validators: None,
+            da_start_height: 1,
+            watch: None,
+        }
+    }
+}
+
+impl Config {
+    // easyfraud [--address <addr>] [--chain-id <id>] [--retain-blocks <n>] [--home <dir>]
+    //           [--snapshot-interval <n>] [--snapshot-keep-recent <n>] [--namespace <hex>]
+    //           [--da <memory|dir:path|celestia:http://host:port>] [--da-auth-token <token>]
+    //           [--sequencer-key <file>] [--sequencer <hex>]
+    //           [--derive | --watch <file:dir|http://host:port/path|rollup:http://host:port>]
+    //           [--genesis <file>] [--validators <file>] [--da-start-height <n>]
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, EasyFraudError> {
+        let mut config = Config::default();
+        let mut args = args.skip(1);
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--address" => config.address = flag_value(&mut args, &flag)?,
+                "--chain-id" => config.chain_id = flag_value(&mut args, &flag)?,
+                "--home" => config.home = flag_value(&mut args, &flag)?,
+                "--snapshot-interval" => config.snapshot_interval = flag_value(&mut args, &flag)?
+                    .parse()
+                    .map_err(|_| EasyFraudError::InvalidArgument(flag.clone()))?,
+                "--snapshot-keep-recent" => config.snapshot_keep_recent = flag_value(&mut args, &flag)?
+                    .parse()
+                    .map_err(|_| EasyFraudError::InvalidArgument(flag.clone()))?,
+                "--namespace" => config.namespace = from_hex(&flag_value(&mut args, &flag)?)
+                    .ok()
+                    .and_then(|id| Namespace::new(0, &id).ok())
+                    .ok_or(EasyFraudError::InvalidArgument(flag.clone()))?,
+                "--da" => config.da = Some(flag_value(&mut args, &flag)?),
+                "--da-auth-token" => config.da_auth_token = Some(flag_value(&mut args, &flag)?),
+                "--sequencer-key" => config.sequencer_key = Some(flag_value(&mut args, &flag)?),
+                "--sequencer" => config.sequencer = Some(from_hex(&flag_value(&mut args, &flag)?)
+                    .ok()
+                    .and_then(|key| VerifyingKey::try_from(&key[..]).ok())
+                    .ok_or(EasyFraudError::InvalidArgument(flag.clone()))?),
+                "--derive" => config.derive = true,
+                "--watch" => config.watch = Some(flag_value(&mut args, &flag)?),
+                "--genesis" => config.genesis = Some(flag_value(&mut args, &flag)?),
+                "--validators" => config.validators = Some(flag_value(&mut args, &flag)?),
+                "--da-start-height" => config.da_start_height = flag_value(&mut args, &flag)?
+                    .parse()
+                    .map_err(|_| EasyFraudError::InvalidArgument(flag.clone()))?,
+                "--retain-blocks" => config.retain_blocks = flag_value(&mut args, &flag)?
+                    .parse()
+                    .map_err(|_| EasyFraudError::InvalidArgument(flag.clone()))?,
+                _ => return Err(EasyFraudError::InvalidArgument(flag.clone())),
+            }
+        }
+        Ok(config)
+    }
+
+    // the key --sequencer-key points at
+    pub fn sequencer_key(&self) -> Result<SigningKey, EasyFraudError> {
+        let path = self.sequencer_key.as_ref()
+            .ok_or(EasyFraudError::InvalidArgument("--sequencer-key".into()))?;
+        let hex = std::fs::read_to_string(path)
+            .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+        let seed: [u8; 32] = from_hex(hex.trim())?
+            .try_into()
+            .map_err(|_| EasyFraudError::InvalidArgument("--sequencer-key".into()))?;
+        Ok(SigningKey::from_bytes(&seed))
+    }
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, EasyFraudError> {
+    args.next().ok_or(EasyFraudError::InvalidArgument(flag.into()))
+}
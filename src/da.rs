@@ -0,0 +1,176 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use celestia_types::{
+    nmt::{Namespace, NS_SIZE},
+    Blob,
+    Commitment,
+};
+
+use crate::celestia::CelestiaDA;
+use crate::errors::EasyFraudError;
+use crate::inclusion::{DataRoots, DataSquare, ShareInclusion};
+use crate::store::{read_optional, write_atomic};
+
+// where blocks get published. celestia in production, the two stand-ins below
+// let the whole post and read back loop run without a node.
+pub trait DataAvailability {
+    // post blobs, they all land at the returned da height
+    fn submit(&mut self, blobs: &[Blob]) -> Result<u64, EasyFraudError>;
+
+    // every blob posted to namespace at height, in the order they were submitted.
+    // a height the da layer hasn't reached yet is an error, not an empty list.
+    fn get_all(&self, height: u64, namespace: Namespace) -> Result<Vec<Blob>, EasyFraudError>;
+
+    // the row and column roots of the square at height, the ones its header commits to
+    fn roots(&self, height: u64) -> Result<DataRoots, EasyFraudError>;
+
+    // proof that share share_index of the blob with commitment is in the square at
+    // height, it covers the rest of the blob's shares in the same row too
+    fn get_proof(&self, height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError>;
+}
+
+// inclusion of a share of the blob with commitment, out of the square every blob
+// posted at height makes up
+fn square_inclusion(blobs: &[Blob], height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError> {
+    if !blobs.iter().any(|blob| blob.namespace == namespace && blob.commitment == *commitment) {
+        return Err(EasyFraudError::BlobNotFound(height));
+    }
+    DataSquare::new(blobs).inclusion(commitment, share_index)
+        .ok_or(EasyFraudError::InvalidInclusion)
+}
+
+// memory                      blobs are kept in memory and gone on restart
+// dir:<path>                  blobs are kept in files under path
+// celestia:http://host:port   a celestia node's rpc endpoint, auth_token is sent along if set
+pub fn open(spec: &str, auth_token: Option<String>) -> Result<Box<dyn DataAvailability + Send>, EasyFraudError> {
+    match spec.split_once(':') {
+        None if spec == "memory" => Ok(Box::new(MemoryDA::default())),
+        Some(("dir", path)) => Ok(Box::new(DirectoryDA::open(path)?)),
+        Some(("celestia", url)) => Ok(Box::new(CelestiaDA::new(url, auth_token)?)),
+        _ => Err(EasyFraudError::InvalidArgument("--da".into())),
+    }
+}
+
+// every submit is its own da height, starting at 1
+#[derive(Default)]
+pub struct MemoryDA {
+    heights: Vec<Vec<Blob>>,
+}
+
+impl DataAvailability for MemoryDA {
+    fn submit(&mut self, blobs: &[Blob]) -> Result<u64, EasyFraudError> {
+        self.heights.push(blobs.to_vec());
+        Ok(self.heights.len() as u64)
+    }
+
+    fn get_all(&self, height: u64, namespace: Namespace) -> Result<Vec<Blob>, EasyFraudError> {
+        Ok(self.blobs(height)?.iter()
+            .filter(|blob| blob.namespace == namespace)
+            .cloned()
+            .collect())
+    }
+
+    fn roots(&self, height: u64) -> Result<DataRoots, EasyFraudError> {
+        Ok(DataSquare::new(self.blobs(height)?).roots())
+    }
+
+    fn get_proof(&self, height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError> {
+        square_inclusion(self.blobs(height)?, height, namespace, commitment, share_index)
+    }
+}
+
+impl MemoryDA {
+    // every blob at height, in any namespace
+    fn blobs(&self, height: u64) -> Result<&[Blob], EasyFraudError> {
+        height.checked_sub(1)
+            .and_then(|i| self.heights.get(i as usize))
+            .map(|blobs| &blobs[..])
+            .ok_or(EasyFraudError::DAHeightNotReached(height))
+    }
+}
+
+// <dir>/<height>  the blobs posted at that height, each as namespace | data length (u32 le) | data.
+// heights are written atomically, so the highest file is always a whole submit.
+// a reader goes by which files are there, another process may be the one submitting.
+pub struct DirectoryDA {
+    dir: PathBuf,
+    // the highest height seen at open or submitted since, below it a missing file is lost
+    head: u64,
+}
+
+impl DirectoryDA {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, EasyFraudError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+        let mut head = 0;
+        for entry in fs::read_dir(&dir).map_err(|e| EasyFraudError::Storage(e.to_string()))? {
+            let entry = entry.map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+            // leftover temp files don't parse and are skipped
+            if let Some(height) = entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) {
+                head = head.max(height);
+            }
+        }
+        Ok(DirectoryDA { dir, head })
+    }
+
+    // every blob at height, in any namespace
+    fn blobs(&self, height: u64) -> Result<Vec<Blob>, EasyFraudError> {
+        if height == 0 {
+            return Err(EasyFraudError::DAHeightNotReached(height));
+        }
+        let buf = match read_optional(&self.dir.join(height.to_string()))? {
+            Some(buf) => buf,
+            None if height > self.head => return Err(EasyFraudError::DAHeightNotReached(height)),
+            None => return Err(EasyFraudError::Storage(format!("da height {} is missing", height))),
+        };
+        let corrupted = || EasyFraudError::Storage(format!("da height {} is corrupted", height));
+        let mut blobs = vec![];
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            if rest.len() < NS_SIZE + 4 {
+                return Err(corrupted());
+            }
+            let namespace = Namespace::from_raw(&rest[..NS_SIZE]).map_err(|_| corrupted())?;
+            let len = u32::from_le_bytes(rest[NS_SIZE..NS_SIZE + 4].try_into().unwrap()) as usize;
+            let data = rest.get(NS_SIZE + 4..NS_SIZE + 4 + len).ok_or_else(corrupted)?;
+            // the commitment isn't stored, Blob::new works it out again
+            blobs.push(Blob::new(namespace, data.to_vec())
+                .map_err(|e| EasyFraudError::Celestia(e.to_string()))?);
+            rest = &rest[NS_SIZE + 4 + len..];
+        }
+        Ok(blobs)
+    }
+}
+
+impl DataAvailability for DirectoryDA {
+    fn submit(&mut self, blobs: &[Blob]) -> Result<u64, EasyFraudError> {
+        let mut buf = vec![];
+        for blob in blobs {
+            buf.extend_from_slice(blob.namespace.as_bytes());
+            buf.extend_from_slice(&(blob.data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&blob.data);
+        }
+        let height = self.head + 1;
+        write_atomic(&self.dir.join(height.to_string()), &buf)?;
+        self.head = height;
+        Ok(height)
+    }
+
+    fn get_all(&self, height: u64, namespace: Namespace) -> Result<Vec<Blob>, EasyFraudError> {
+        Ok(self.blobs(height)?.into_iter()
+            .filter(|blob| blob.namespace == namespace)
+            .collect())
+    }
+
+    fn roots(&self, height: u64) -> Result<DataRoots, EasyFraudError> {
+        Ok(DataSquare::new(&self.blobs(height)?).roots())
+    }
+
+    fn get_proof(&self, height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError> {
+        square_inclusion(&self.blobs(height)?, height, namespace, commitment, share_index)
+    }
+}
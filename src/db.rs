@@ -0,0 +1,229 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+use monotree::{
+    hasher::Blake3,
+    Database,
+    Errors,
+    Hasher,
+};
+
+use crate::utils::{from_hex, to_hex};
+
+// pass this as the path to keep the tree in memory
+pub const MEMORY_DB: &str = ":memory:";
+// paths starting with this carry the hex of a witness, see witness_path
+const WITNESS_DB: &str = "witness:";
+
+thread_local! {
+    // what trees on this thread touched while record_reads is running
+    static RECORDING: RefCell<Option<Recording>> = RefCell::new(None);
+}
+
+#[derive(Default)]
+struct Recording {
+    read: BTreeMap<Vec<u8>, Vec<u8>>,
+    // nodes written by the recorded code itself don't need to be witnessed
+    written: HashSet<Vec<u8>>,
+}
+
+enum Backend {
+    Memory(HashMap<Vec<u8>, Vec<u8>>),
+    // only the nodes of a witness. they're keyed by their own hash, which is
+    // how monotree addresses nodes, so a witness can't lie about what's
+    // under a root. reading anything else is an error rather than a missing leaf.
+    Witness(HashMap<Vec<u8>, Vec<u8>>),
+    Sled(sled::Db),
+    // Database::new can't fail, so a db that didn't open reports it on every access
+    Failed(String),
+}
+
+// monotree node store, either in memory or in a sled database on disk.
+// writes between init_batch and finish_batch are held back and land in one
+// atomic sled batch, which is how a block gets committed all or nothing.
+pub struct TreeDB {
+    backend: Backend,
+    batch: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl TreeDB {
+    // open the db once up front so State::open can report a useful error
+    pub fn check(dbpath: &str) -> Result<(), String> {
+        sled::open(dbpath).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+// run f and return every tree node it read that it didn't write first, in key
+// order. these nodes are enough to repeat the same reads and writes on a tree
+// opened at witness_path.
+pub fn record_reads<T>(f: impl FnOnce() -> T) -> (T, Vec<Vec<u8>>) {
+    RECORDING.with(|recording| *recording.borrow_mut() = Some(Recording::default()));
+    let result = f();
+    let nodes = RECORDING.with(|recording| recording.borrow_mut().take())
+        .unwrap_or_default()
+        .read
+        .into_values()
+        .collect();
+    (result, nodes)
+}
+
+// Database::new only gets a path, so a witness tree is opened from a path
+// that carries the nodes: each one is a u32 le length and the node bytes
+pub fn witness_path(nodes: &[Vec<u8>]) -> String {
+    let mut buf = vec![];
+    for node in nodes {
+        buf.extend_from_slice(&(node.len() as u32).to_le_bytes());
+        buf.extend_from_slice(node);
+    }
+    format!("{}{}", WITNESS_DB, to_hex(&buf))
+}
+
+fn witness_nodes(hex: &str) -> Result<HashMap<Vec<u8>, Vec<u8>>, String> {
+    let hasher = Blake3::new();
+    let mut data = &from_hex(hex).map_err(|e| e.to_string())?[..];
+    let mut nodes = HashMap::new();
+    while !data.is_empty() {
+        let len = data.get(..4)
+            .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+            .ok_or("truncated witness")?;
+        let node = data.get(4..4 + len).ok_or("truncated witness")?;
+        nodes.insert(hasher.digest(node).to_vec(), node.to_vec());
+        data = &data[4 + len..];
+    }
+    Ok(nodes)
+}
+
+fn record_read(key: &[u8], value: &Option<Vec<u8>>) {
+    RECORDING.with(|recording| {
+        if let (Some(recording), Some(value)) = (recording.borrow_mut().as_mut(), value) {
+            if !recording.written.contains(key) {
+                recording.read.insert(key.to_vec(), value.clone());
+            }
+        }
+    });
+}
+
+fn record_write(key: &[u8]) {
+    RECORDING.with(|recording| {
+        if let Some(recording) = recording.borrow_mut().as_mut() {
+            recording.written.insert(key.to_vec());
+        }
+    });
+}
+
+fn db_error(e: impl ToString) -> Errors {
+    Errors::new(&e.to_string())
+}
+
+impl Database for TreeDB {
+    fn new(dbpath: &str) -> Self {
+        let backend = if dbpath == MEMORY_DB {
+            Backend::Memory(HashMap::new())
+        } else if let Some(hex) = dbpath.strip_prefix(WITNESS_DB) {
+            match witness_nodes(hex) {
+                Ok(nodes) => Backend::Witness(nodes),
+                Err(e) => Backend::Failed(e),
+            }
+        } else {
+            match sled::open(dbpath) {
+                Ok(db) => Backend::Sled(db),
+                Err(e) => Backend::Failed(e.to_string()),
+            }
+        };
+        TreeDB {
+            backend,
+            batch: None,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        let value = match self.batch.as_ref().and_then(|batch| batch.get(key)) {
+            Some(value) => value.clone(),
+            None => match &self.backend {
+                Backend::Memory(map) => map.get(key).cloned(),
+                Backend::Witness(map) => Some(map.get(key).cloned()
+                    .ok_or_else(|| db_error("node is not in the witness"))?),
+                Backend::Sled(db) => db.get(key)
+                    .map(|value| value.map(|v| v.to_vec()))
+                    .map_err(db_error)?,
+                Backend::Failed(e) => return Err(db_error(e)),
+            },
+        };
+        record_read(key, &value);
+        Ok(value)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        record_write(key);
+        if let Some(batch) = self.batch.as_mut() {
+            batch.insert(key.to_vec(), Some(value));
+            return Ok(());
+        }
+        match &mut self.backend {
+            Backend::Memory(map) | Backend::Witness(map) => {
+                map.insert(key.to_vec(), value);
+                Ok(())
+            }
+            Backend::Sled(db) => db.insert(key, value)
+                .map(|_| ())
+                .map_err(db_error),
+            Backend::Failed(e) => Err(db_error(e)),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        if let Some(batch) = self.batch.as_mut() {
+            batch.insert(key.to_vec(), None);
+            return Ok(());
+        }
+        match &mut self.backend {
+            Backend::Memory(map) | Backend::Witness(map) => {
+                map.remove(key);
+                Ok(())
+            }
+            Backend::Sled(db) => db.remove(key)
+                .map(|_| ())
+                .map_err(db_error),
+            Backend::Failed(e) => Err(db_error(e)),
+        }
+    }
+
+    // starting a batch drops whatever an unfinished one held, those nodes
+    // only ever belonged to speculative roots
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        self.batch = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        let batch = match self.batch.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        match &mut self.backend {
+            Backend::Memory(map) | Backend::Witness(map) => {
+                for (key, value) in batch {
+                    match value {
+                        Some(value) => map.insert(key, value),
+                        None => map.remove(&key),
+                    };
+                }
+                Ok(())
+            }
+            Backend::Sled(db) => {
+                let mut sled_batch = sled::Batch::default();
+                for (key, value) in batch {
+                    match value {
+                        Some(value) => sled_batch.insert(key, value),
+                        None => sled_batch.remove(key),
+                    }
+                }
+                db.apply_batch(sled_batch).map_err(db_error)?;
+                db.flush().map(|_| ()).map_err(db_error)
+            }
+            Backend::Failed(e) => Err(db_error(e)),
+        }
+    }
+}
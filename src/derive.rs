@@ -0,0 +1,177 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use celestia_types::Blob;
+use ed25519_dalek::VerifyingKey;
+use log::{info, warn};
+use tendermint::{v0_38::abci::request::InitChain as RequestInitChain, validator, PublicKey};
+
+use crate::blob::decode_signed_block;
+use crate::block::OutgoingBlock;
+use crate::config::Config;
+use crate::da::{self, DataAvailability};
+use crate::errors::EasyFraudError;
+use crate::snapshot::validators_from_bytes;
+use crate::state::State;
+use crate::transaction::Tx;
+
+// how long to wait for the da layer to produce the next height
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// a sovereign full node: state rebuilt from nothing but the blocks the sequencer
+// published to the rollup's namespace, CometBFT is never asked for anything
+pub struct Derivation {
+    pub state: State,
+    pub da: Box<dyn DataAvailability + Send>,
+    // only blocks signed by this key are read
+    pub sequencer: VerifyingKey,
+    // next da height to read
+    pub da_height: u64,
+}
+
+impl Derivation {
+    // picks up at the da height state's store left off at, or start_height
+    pub fn new(state: State, da: Box<dyn DataAvailability + Send>, sequencer: VerifyingKey, start_height: u64) -> Result<Self, EasyFraudError> {
+        let da_height = match &state.store {
+            Some(store) => store.load_da_height()?.unwrap_or(start_height),
+            None => start_height,
+        };
+        Ok(Derivation {
+            state,
+            da,
+            sequencer,
+            da_height,
+        })
+    }
+
+    // apply every block the sequencer published at the next da height, false if the da
+    // layer isn't there yet. a block that doesn't check out is skipped, state stays where
+    // it was for a later block at that height to take its place.
+    pub fn step(&mut self) -> Result<bool, EasyFraudError> {
+        self.step_with(|derivation, _, height, _, e| {
+            warn!("skipped block {} at da height {}: {}", height, derivation.da_height, e);
+            Ok(())
+        })
+    }
+
+    // step, handing every block that doesn't check out to rejected, along with the blob
+    // it came in and why. an error from rejected ends the step before the da height is done.
+    pub fn step_with(&mut self, mut rejected: impl FnMut(&mut Self, &Blob, u64, &OutgoingBlock, EasyFraudError) -> Result<(), EasyFraudError>) -> Result<bool, EasyFraudError> {
+        let blobs = match self.da.get_all(self.da_height, self.state.namespace) {
+            Err(EasyFraudError::DAHeightNotReached(_)) => return Ok(false),
+            blobs => blobs?,
+        };
+        for blob in blobs {
+            // anyone can post to a namespace, only what the sequencer signed is a block
+            if let Ok((height, block)) = decode_signed_block(&blob.data, &self.state.chain_id, &self.sequencer) {
+                match apply_block(&mut self.state, height, &block) {
+                    Err(e) if rejects_block(&e) => rejected(self, &blob, height, &block, e)?,
+                    result => result?,
+                }
+            }
+        }
+        self.da_height += 1;
+        if let Some(store) = &self.state.store {
+            store.save_da_height(self.da_height)?;
+        }
+        Ok(true)
+    }
+
+    // step until the da layer runs out, returns the rollup height reached
+    pub fn sync(&mut self) -> Result<u64, EasyFraudError> {
+        while self.step()? {}
+        Ok(self.state.height)
+    }
+}
+
+// re-execute a published block on top of state and commit it. every pair has to run
+// and land on the ISR it claims, and the last one on the header's app hash.
+// a block that doesn't leaves state where it was.
+pub fn apply_block(state: &mut State, height: u64, block: &OutgoingBlock) -> Result<(), EasyFraudError> {
+    if height <= state.height {
+        // posted again, a sequencer does that when it replays a block after a crash
+        return match state.app_hashes.get(&height) {
+            Some(app_hash) if Some(*app_hash) != block.header.apphash => Err(EasyFraudError::ConflictingBlock(height)),
+            // matches, or pruned and there's nothing left to compare with
+            _ => Ok(()),
+        };
+    }
+    if height != state.height + 1 {
+        return Err(EasyFraudError::MissingBlock(state.height + 1));
+    }
+
+    state.tree.prepare();
+    // the proposer was picked by CometBFT, all the blob has of it is where its fees went
+    state.fee_recipient = block.header.fee_recipient;
+    for (index, pair) in block.pairs.iter().enumerate() {
+        let isr = Tx::decode(&pair.0)
+            .and_then(|tx| state.execute_transaction(&tx));
+        if !matches!(isr, Ok(Some(isr)) if isr == pair.1) {
+            state.revert_volatile();
+            return Err(EasyFraudError::FraudulentBlock { height, index });
+        }
+    }
+    if state.root != block.header.apphash {
+        state.revert_volatile();
+        return Err(EasyFraudError::WrongAppHash(height));
+    }
+    state.commit()?;
+    Ok(())
+}
+
+// errors that mean the block itself is bad, as opposed to storage failing us
+fn rejects_block(e: &EasyFraudError) -> bool {
+    matches!(e,
+        EasyFraudError::FraudulentBlock { .. }
+        | EasyFraudError::WrongAppHash(_)
+        | EasyFraudError::MissingBlock(_)
+        | EasyFraudError::ConflictingBlock(_))
+}
+
+// state set up from genesis if it's new, following the da layer config points at
+pub fn open(mut state: State, config: &Config) -> Result<Derivation, EasyFraudError> {
+    if !state.initialized {
+        let genesis = config.genesis.as_ref()
+            .ok_or(EasyFraudError::InvalidArgument("--genesis".into()))?;
+        let app_state_bytes = std::fs::read(genesis)
+            .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+        let mut init_chain = RequestInitChain::default();
+        init_chain.chain_id = state.chain_id.clone();
+        init_chain.app_state_bytes = app_state_bytes.into();
+        if let Some(path) = &config.validators {
+            let buf = std::fs::read(path)
+                .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+            init_chain.validators = validators_from_bytes(&buf)
+                .map_err(|_| EasyFraudError::InvalidArgument("--validators".into()))?
+                .values()
+                .map(|key| PublicKey::from_raw_ed25519(key)
+                    .map(|pub_key| validator::Update { pub_key, power: 1u32.into() })
+                    .ok_or(EasyFraudError::InvalidArgument("--validators".into())))
+                .collect::<Result<Vec<validator::Update>, EasyFraudError>>()?;
+        }
+        state.init_chain(init_chain)?;
+    }
+    let spec = config.da.as_ref()
+        .ok_or(EasyFraudError::InvalidArgument("--da".into()))?;
+    let sequencer = config.sequencer
+        .ok_or(EasyFraudError::InvalidArgument("--sequencer".into()))?;
+    let da = da::open(spec, config.da_auth_token.clone())?;
+    Derivation::new(state, da, sequencer, config.da_start_height)
+}
+
+// the --derive mode, follow the da layer until stop is set
+pub fn run(state: State, config: Config, stop: &AtomicBool) -> Result<(), EasyFraudError> {
+    let mut derivation = open(state, &config)?;
+    while !stop.load(Ordering::Relaxed) {
+        let before = derivation.state.height;
+        let height = derivation.sync()?;
+        if height != before {
+            info!("derived height {}, next da height {}", height, derivation.da_height);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
@@ -32,4 +32,163 @@ pub enum EasyFraudError {
     SerializePairsError,
     #[error("Could not deserialize pairs")]
     DeserializePairsError,
+    #[error("Unhandled request")]
+    UnhandledRequest,
+    #[error("State lock poisoned")]
+    StateLockPoisoned,
+    #[error("Invalid argument {0}")]
+    InvalidArgument(String),
+    #[error("Invalid block height")]
+    InvalidHeight,
+    #[error("Transaction amount must be greater than zero")]
+    ZeroAmount,
+    #[error("Sender and recipient must differ")]
+    SelfTransfer,
+    #[error("Sender balance too low")]
+    InsufficientBalance,
+    #[error("Recipient balance would overflow")]
+    BalanceOverflow,
+    #[error("Claimed intermediate state root does not match")]
+    IsrMismatch,
+    #[error("Storage error: {0}")]
+    Storage(String),
+    #[error("Invalid hex")]
+    InvalidHex,
+    #[error("Invalid merkle proof")]
+    InvalidProof,
+    #[error("No committed state at height {0}")]
+    UnknownHeight(u64),
+    #[error("Invalid snapshot")]
+    InvalidSnapshot,
+    #[error("Block has no pair {0}")]
+    InvalidPairIndex(usize),
+    #[error("Pair {0} is already fraudulent")]
+    EarlierFraud(usize),
+    #[error("Pair {0} is not fraudulent")]
+    NoFraud(usize),
+    #[error("Invalid fraud proof")]
+    InvalidFraudProof,
+    #[error("Share is not in the blob")]
+    InvalidInclusion,
+    #[error("Pair {0} was not published")]
+    PairNotPublished(usize),
+    #[error("Unsupported blob version {0}")]
+    UnsupportedBlobVersion(u8),
+    #[error("Invalid blob header")]
+    InvalidBlobHeader,
+    #[error("Blob truncated, expected {expected} bytes but got {actual}")]
+    TruncatedBlob { expected: usize, actual: usize },
+    #[error("Blob has data after its end")]
+    TrailingBlobData,
+    #[error("Celestia error: {0}")]
+    Celestia(String),
+    #[error("Share is not in the rollup namespace")]
+    ShareNamespaceMismatch,
+    #[error("No blob with that commitment at DA height {0}")]
+    BlobNotFound(u64),
+    #[error("DA height {0} is not there yet")]
+    DAHeightNotReached(u64),
+    #[error("Block is not signed by the sequencer")]
+    InvalidBlockSignature,
+    #[error("Invalid base64")]
+    InvalidBase64,
+    #[error("HTTP request failed: {0}")]
+    Http(String),
+    #[error("Block {height} is fraudulent at pair {index}")]
+    FraudulentBlock { height: u64, index: usize },
+    #[error("Block {0} does not end on its header app hash")]
+    WrongAppHash(u64),
+    #[error("Block {0} is missing")]
+    MissingBlock(u64),
+    #[error("Block {0} was published again with another app hash")]
+    ConflictingBlock(u64),
+    #[error("Fraud report was rejected: {0}")]
+    ReportRejected(String),
+    #[error("Expected nonce {expected} but got {actual}")]
+    InvalidNonce { expected: u64, actual: u64 },
+    #[error("Transaction has no nonce, sign it again")]
+    LegacyTransaction,
+    #[error("Unsupported transaction envelope version {0}")]
+    UnsupportedEnvelopeVersion(u8),
+    #[error("Unknown transaction kind {0}")]
+    UnknownTransactionKind(u8),
+    #[error("Transaction envelope length does not match")]
+    InvalidEnvelope,
+    #[error("Account is reserved")]
+    ReservedAccount,
+    #[error("Expected block {expected} but the proof is about block {actual}")]
+    WrongBlockHeight { expected: u64, actual: u64 },
+    #[error("Account does not exist, monotree can't prove that it doesn't")]
+    AccountNotFound,
+}
+
+impl EasyFraudError {
+    // ABCI response code for this error. 0 is success, so codes start at 1.
+    // clients match on these, so a code is never reused or renumbered: a removed
+    // variant leaves a gap and new ones go at the end.
+    pub fn code(&self) -> u32 {
+        match self {
+            EasyFraudError::TransactionDeserializationError => 1,
+            EasyFraudError::SigningError => 2,
+            EasyFraudError::ChainIDMismatch => 3,
+            EasyFraudError::InvalidGenesisHeight => 4,
+            EasyFraudError::GenesisAccountDeserialization => 5,
+            EasyFraudError::TreeInsertionError => 6,
+            EasyFraudError::NullApphash => 7,
+            EasyFraudError::InvalidGenesisAppHash => 8,
+            EasyFraudError::TreeGetError => 9,
+            EasyFraudError::SenderNotInitialized => 10,
+            EasyFraudError::CouldNotRevert => 11,
+            EasyFraudError::InvalidSignature => 12,
+            EasyFraudError::NoRoot => 13,
+            EasyFraudError::SerializePairsError => 14,
+            EasyFraudError::DeserializePairsError => 15,
+            EasyFraudError::UnhandledRequest => 16,
+            EasyFraudError::StateLockPoisoned => 17,
+            EasyFraudError::InvalidArgument(_) => 18,
+            EasyFraudError::InvalidHeight => 19,
+            EasyFraudError::ZeroAmount => 20,
+            EasyFraudError::SelfTransfer => 21,
+            EasyFraudError::InsufficientBalance => 22,
+            EasyFraudError::BalanceOverflow => 23,
+            EasyFraudError::IsrMismatch => 24,
+            EasyFraudError::Storage(_) => 25,
+            // 26 and 27 were MissingBlock and ReplayMismatch from before the tree was persisted
+            EasyFraudError::InvalidHex => 28,
+            EasyFraudError::InvalidProof => 29,
+            EasyFraudError::UnknownHeight(_) => 30,
+            EasyFraudError::InvalidSnapshot => 31,
+            EasyFraudError::InvalidPairIndex(_) => 32,
+            EasyFraudError::EarlierFraud(_) => 33,
+            EasyFraudError::NoFraud(_) => 34,
+            EasyFraudError::InvalidFraudProof => 35,
+            EasyFraudError::InvalidInclusion => 36,
+            EasyFraudError::PairNotPublished(_) => 37,
+            EasyFraudError::UnsupportedBlobVersion(_) => 38,
+            EasyFraudError::InvalidBlobHeader => 39,
+            EasyFraudError::TruncatedBlob { .. } => 40,
+            EasyFraudError::TrailingBlobData => 41,
+            EasyFraudError::Celestia(_) => 42,
+            EasyFraudError::ShareNamespaceMismatch => 43,
+            // 44 and 45 were UnsupportedShareVersion and InvalidSequenceStart, from reading a blob back out of its shares
+            EasyFraudError::BlobNotFound(_) => 46,
+            EasyFraudError::DAHeightNotReached(_) => 47,
+            EasyFraudError::InvalidBlockSignature => 48,
+            EasyFraudError::InvalidBase64 => 49,
+            EasyFraudError::Http(_) => 50,
+            EasyFraudError::FraudulentBlock { .. } => 51,
+            EasyFraudError::WrongAppHash(_) => 52,
+            EasyFraudError::MissingBlock(_) => 53,
+            EasyFraudError::ConflictingBlock(_) => 54,
+            EasyFraudError::ReportRejected(_) => 55,
+            EasyFraudError::InvalidNonce { .. } => 56,
+            EasyFraudError::LegacyTransaction => 57,
+            EasyFraudError::UnsupportedEnvelopeVersion(_) => 58,
+            EasyFraudError::UnknownTransactionKind(_) => 59,
+            EasyFraudError::InvalidEnvelope => 60,
+            EasyFraudError::ReservedAccount => 61,
+            EasyFraudError::WrongBlockHeight { .. } => 62,
+            EasyFraudError::AccountNotFound => 63,
+        }
+    }
 }
\ No newline at end of file
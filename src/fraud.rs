@@ -0,0 +1,382 @@
+use celestia_types::{nmt::Namespace, Share};
+use ed25519_dalek::VerifyingKey;
+use monotree::{Hash, Monotree};
+
+use crate::blob::{offset_location, pair_location, read_header, read_pair, BlobHeader, SHARE_SIZE};
+use crate::block::{OutgoingBlock, SignedTxnISRPair};
+use crate::db::{record_reads, witness_path, TreeDB};
+use crate::errors::EasyFraudError;
+use crate::inclusion::{merkle_aunts, ShareInclusion, NAMESPACED_HASH_SIZE};
+use crate::state::{apply_tx, State};
+use crate::transaction::Tx;
+
+// what a fraud proof claims about the pair at its index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FraudKind {
+    // the transaction runs, but not to the ISR the pair claims
+    WrongIsr = 0,
+    // the transaction should have been rejected, it has no ISR at all
+    InvalidTransaction = 1,
+}
+
+// proof that the pair at index is fraudulent, in the way kind says. see docs/protocol.md
+#[derive(Debug, PartialEq)]
+pub struct FraudProof {
+    pub kind: FraudKind,
+    pub index: u32,
+    // where the blob's first share is in the original data square, counting row by row
+    pub blob_start: u32,
+    // the transaction's envelope
+    pub transaction: Vec<u8>,
+    pub prev_isr: Hash,
+    pub claimed_isr: Hash,
+    // merkle proofs of the pair and the one before it, empty for the first pair
+    pub pair_aunts: Vec<[u8; 32]>,
+    pub prev_pair_aunts: Vec<[u8; 32]>,
+    pub witness: Vec<Vec<u8>>,
+    pub inclusions: Vec<ShareInclusion>,
+}
+
+impl FraudProof {
+    // state has to be at the root the block was built on, shares are the blob it was published as
+    pub fn generate(state: &mut State, block: &OutgoingBlock, index: usize, shares: &[Share], prove: impl Fn(usize) -> Result<ShareInclusion, EasyFraudError>) -> Result<Self, EasyFraudError> {
+        if index >= block.pairs.len() {
+            return Err(EasyFraudError::InvalidPairIndex(index));
+        }
+        let read = |share: usize| shares.get(share).map(|share| &share.data);
+        let header = read_header(read)
+            .filter(|header| header.fee_recipient == block.header.fee_recipient)
+            .ok_or(EasyFraudError::InvalidBlobHeader)?;
+        // the header is in the first share, its run in the square starts where the blob does
+        let mut inclusions = vec![prove(0)?];
+        let blob_start = inclusions[0].first();
+        for i in index.saturating_sub(1)..=index {
+            let published = read_pair(i, header.count, read);
+            if published.map(|pair| pair.serialize()) != Some(block.pairs[i].serialize()) {
+                return Err(EasyFraudError::PairNotPublished(i));
+            }
+            // the pair, and the offsets that say where it is
+            let location = pair_location(i, header.count, read).ok_or(EasyFraudError::PairNotPublished(i))?;
+            for (share, _) in offset_location(i).into_iter().chain(location) {
+                if inclusions.iter().all(|inclusion| inclusion.share(blob_start + share).is_none()) {
+                    inclusions.push(prove(share)?);
+                }
+            }
+        }
+
+        state.tree.prepare();
+        let result = Self::build(state, block, index, blob_start, inclusions);
+        state.revert_volatile();
+        result
+    }
+
+    fn build(state: &mut State, block: &OutgoingBlock, index: usize, blob_start: usize, inclusions: Vec<ShareInclusion>) -> Result<Self, EasyFraudError> {
+        state.fee_recipient = block.header.fee_recipient;
+        for (i, pair) in block.pairs[..index].iter().enumerate() {
+            let isr = Tx::decode(&pair.0)
+                .and_then(|tx| state.execute_transaction(&tx));
+            if !matches!(isr, Ok(Some(isr)) if isr == pair.1) {
+                return Err(EasyFraudError::EarlierFraud(i));
+            }
+        }
+
+        let pair = &block.pairs[index];
+        let prev_isr = state.root.ok_or(EasyFraudError::NullApphash)?;
+        let pairs = block.pairs.iter().map(SignedTxnISRPair::serialize).collect::<Vec<Vec<u8>>>();
+        // an envelope that doesn't decode is as invalid as a transaction that doesn't run
+        let (transfer, witness) = record_reads(|| Tx::decode(&pair.0)
+            .and_then(|tx| apply_tx(&mut state.tree, &state.chain_id, Some(&prev_isr), block.header.fee_recipient.as_ref(), &tx)));
+        let kind = match transfer {
+            Ok(transfer) if transfer.root == Some(pair.1) => return Err(EasyFraudError::NoFraud(index)),
+            Ok(_) => FraudKind::WrongIsr,
+            Err(e) if rejects_transaction(&e) => FraudKind::InvalidTransaction,
+            Err(e) => return Err(e),
+        };
+        Ok(FraudProof {
+            kind,
+            index: index as u32,
+            blob_start: blob_start as u32,
+            transaction: pair.0.clone(),
+            prev_isr,
+            claimed_isr: pair.1,
+            pair_aunts: merkle_aunts(&pairs, index),
+            prev_pair_aunts: match index {
+                0 => vec![],
+                _ => merkle_aunts(&pairs, index - 1),
+            },
+            witness,
+            inclusions,
+        })
+    }
+
+    // the pair at index as published, out of the header's count, if the inclusions cover it
+    fn published_pair(&self, header: &BlobHeader, index: usize) -> Option<SignedTxnISRPair> {
+        read_pair(index, header.count, |share| self.included_share(share))
+    }
+
+    // the header as published, if the inclusions cover it
+    fn published_header(&self) -> Option<BlobHeader> {
+        read_header(|share| self.included_share(share))
+    }
+
+    // share of the blob, if the inclusions cover it
+    fn included_share(&self, share: usize) -> Option<&[u8; SHARE_SIZE]> {
+        let index = self.blob_start as usize + share;
+        self.inclusions.iter().find_map(|inclusion| inclusion.share(index))
+    }
+
+    // kind u8 | index u32 le | blob start u32 le | tx length u32 le | tx | prev isr 32 |
+    // claimed isr 32 | aunt count u32 le | aunts 32 each | prev aunt count u32 le |
+    // prev aunts 32 each | node count u32 le, then each node as a u32 le length and its bytes,
+    // then an inclusion count u32 le and each inclusion as square width u32 le | row u32 le |
+    // column u32 le | share count u32 le | shares 512 each | row root 90 | sibling count u32 le |
+    // siblings 90 each | aunt count u32 le | aunts 32 each
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = vec![self.kind as u8];
+        buf.extend_from_slice(&self.index.to_le_bytes());
+        buf.extend_from_slice(&self.blob_start.to_le_bytes());
+        buf.extend_from_slice(&(self.transaction.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.transaction);
+        buf.extend_from_slice(&self.prev_isr);
+        buf.extend_from_slice(&self.claimed_isr);
+        for aunts in [&self.pair_aunts, &self.prev_pair_aunts] {
+            buf.extend_from_slice(&(aunts.len() as u32).to_le_bytes());
+            for aunt in aunts {
+                buf.extend_from_slice(aunt);
+            }
+        }
+        buf.extend_from_slice(&(self.witness.len() as u32).to_le_bytes());
+        for node in &self.witness {
+            buf.extend_from_slice(&(node.len() as u32).to_le_bytes());
+            buf.extend_from_slice(node);
+        }
+        buf.extend_from_slice(&(self.inclusions.len() as u32).to_le_bytes());
+        for inclusion in &self.inclusions {
+            buf.extend_from_slice(&inclusion.square_width.to_le_bytes());
+            buf.extend_from_slice(&inclusion.row.to_le_bytes());
+            buf.extend_from_slice(&inclusion.column.to_le_bytes());
+            buf.extend_from_slice(&(inclusion.shares.len() as u32).to_le_bytes());
+            for share in &inclusion.shares {
+                buf.extend_from_slice(share);
+            }
+            buf.extend_from_slice(&inclusion.row_root);
+            buf.extend_from_slice(&(inclusion.nmt_siblings.len() as u32).to_le_bytes());
+            for sibling in &inclusion.nmt_siblings {
+                buf.extend_from_slice(sibling);
+            }
+            buf.extend_from_slice(&(inclusion.aunts.len() as u32).to_le_bytes());
+            for aunt in &inclusion.aunts {
+                buf.extend_from_slice(aunt);
+            }
+        }
+        buf
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, EasyFraudError> {
+        let mut reader = Reader(data);
+        let kind = match reader.array::<1>()? {
+            [0] => FraudKind::WrongIsr,
+            [1] => FraudKind::InvalidTransaction,
+            _ => return Err(EasyFraudError::InvalidFraudProof),
+        };
+        let index = reader.u32()?;
+        let blob_start = reader.u32()?;
+        let len = reader.u32()? as usize;
+        let transaction = reader.take(len)?.to_vec();
+        let prev_isr = reader.array()?;
+        let claimed_isr = reader.array()?;
+        let mut aunts = || {
+            let count = reader.u32()?;
+            (0..count)
+                .map(|_| reader.array())
+                .collect::<Result<Vec<[u8; 32]>, EasyFraudError>>()
+        };
+        let pair_aunts = aunts()?;
+        let prev_pair_aunts = aunts()?;
+        let count = reader.u32()?;
+        let witness = (0..count)
+            .map(|_| {
+                let len = reader.u32()? as usize;
+                reader.take(len).map(|node| node.to_vec())
+            })
+            .collect::<Result<Vec<Vec<u8>>, EasyFraudError>>()?;
+        let count = reader.u32()?;
+        let inclusions = (0..count)
+            .map(|_| {
+                let square_width = reader.u32()?;
+                let row = reader.u32()?;
+                let column = reader.u32()?;
+                let shares = reader.u32()?;
+                let shares = (0..shares)
+                    .map(|_| reader.array())
+                    .collect::<Result<Vec<[u8; SHARE_SIZE]>, EasyFraudError>>()?;
+                let row_root = reader.take(NAMESPACED_HASH_SIZE)?.to_vec();
+                let siblings = reader.u32()?;
+                let nmt_siblings = (0..siblings)
+                    .map(|_| reader.take(NAMESPACED_HASH_SIZE).map(|sibling| sibling.to_vec()))
+                    .collect::<Result<Vec<Vec<u8>>, EasyFraudError>>()?;
+                let aunts = reader.u32()?;
+                let aunts = (0..aunts)
+                    .map(|_| reader.array())
+                    .collect::<Result<Vec<[u8; 32]>, EasyFraudError>>()?;
+                Ok(ShareInclusion {
+                    square_width,
+                    row,
+                    column,
+                    shares,
+                    nmt_siblings,
+                    row_root,
+                    aunts,
+                })
+            })
+            .collect::<Result<Vec<ShareInclusion>, EasyFraudError>>()?;
+        if !reader.0.is_empty() {
+            return Err(EasyFraudError::InvalidFraudProof);
+        }
+        Ok(FraudProof {
+            kind,
+            index,
+            blob_start,
+            transaction,
+            prev_isr,
+            claimed_isr,
+            pair_aunts,
+            prev_pair_aunts,
+            witness,
+            inclusions,
+        })
+    }
+}
+
+// a fraud proof along with where to check it: the block's height, and the da height
+// it was published at. whoever checks it knows the chain id, the namespace, and the
+// app hash at height - 1 the first pair starts from, themselves, and takes the data
+// root from the da layer's header at da_height.
+pub struct FraudReport {
+    pub height: u64,
+    pub da_height: u64,
+    pub proof: FraudProof,
+}
+
+impl FraudReport {
+    // height u64 le | da height u64 le | proof
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = self.height.to_le_bytes().to_vec();
+        buf.extend_from_slice(&self.da_height.to_le_bytes());
+        buf.extend_from_slice(&self.proof.serialize());
+        buf
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FraudVerdict {
+    // the transaction takes prev_isr somewhere other than the claimed ISR
+    WrongIsr { computed_isr: Hash },
+    // the transaction is rejected on prev_isr with this error code
+    InvalidTransaction { code: u32 },
+    // the pair is fine, whoever sent the proof was wrong
+    Honest,
+}
+
+// errors that mean the transaction itself is bad, as opposed to the tree failing us
+fn rejects_transaction(e: &EasyFraudError) -> bool {
+    matches!(e,
+        EasyFraudError::TransactionDeserializationError
+        | EasyFraudError::LegacyTransaction
+        | EasyFraudError::UnsupportedEnvelopeVersion(_)
+        | EasyFraudError::UnknownTransactionKind(_)
+        | EasyFraudError::InvalidEnvelope
+        | EasyFraudError::InvalidSignature
+        | EasyFraudError::InvalidNonce { .. }
+        | EasyFraudError::ZeroAmount
+        | EasyFraudError::SelfTransfer
+        | EasyFraudError::ReservedAccount
+        | EasyFraudError::SenderNotInitialized
+        | EasyFraudError::InsufficientBalance
+        | EasyFraudError::BalanceOverflow)
+}
+
+// check a serialized FraudProof against what the verifier already trusts, see docs/protocol.md
+pub fn verify_fraud_proof(proof: &[u8], chain_id: &str, namespace: Namespace, sequencer: &VerifyingKey, data_root: &[u8; 32], height: u64, start_root: &Hash) -> Result<FraudVerdict, EasyFraudError> {
+    let proof = FraudProof::deserialize(proof)?;
+    if proof.inclusions.windows(2).any(|pair| pair[0].square_width != pair[1].square_width) {
+        return Err(EasyFraudError::InvalidInclusion);
+    }
+    for inclusion in &proof.inclusions {
+        inclusion.verify(namespace, data_root)?;
+    }
+    let index = proof.index as usize;
+    // the header says how many pairs there are, and so where the offset table ends
+    let header = proof.published_header().ok_or(EasyFraudError::InvalidBlobHeader)?;
+    if index >= header.count {
+        return Err(EasyFraudError::InvalidPairIndex(index));
+    }
+    let pair = match proof.published_pair(&header, index) {
+        Some(pair) if pair.0 == proof.transaction && pair.1 == proof.claimed_isr => pair,
+        _ => return Err(EasyFraudError::PairNotPublished(index)),
+    };
+    let prev_pair = match index {
+        0 => None,
+        _ => proof.published_pair(&header, index - 1),
+    };
+    let prev_isr = match index {
+        0 => Some(*start_root),
+        _ => prev_pair.as_ref().map(|pair| pair.1),
+    };
+    if prev_isr != Some(proof.prev_isr) {
+        return Err(EasyFraudError::PairNotPublished(index.saturating_sub(1)));
+    }
+    header.verify(chain_id, sequencer)?;
+    if header.height != height {
+        return Err(EasyFraudError::WrongBlockHeight { expected: height, actual: header.height });
+    }
+    if !header.commits_to(index, &pair, &proof.pair_aunts) {
+        return Err(EasyFraudError::PairNotPublished(index));
+    }
+    if let Some(prev_pair) = prev_pair {
+        if !header.commits_to(index - 1, &prev_pair, &proof.prev_pair_aunts) {
+            return Err(EasyFraudError::PairNotPublished(index - 1));
+        }
+    }
+
+    let mut tree: Monotree<TreeDB> = Monotree::new(&witness_path(&proof.witness));
+    let transfer = match Tx::decode(&proof.transaction).and_then(|tx| apply_tx(&mut tree, chain_id, Some(&proof.prev_isr), header.fee_recipient.as_ref(), &tx)) {
+        Ok(transfer) => transfer,
+        Err(EasyFraudError::TreeGetError | EasyFraudError::TreeInsertionError) => {
+            return Err(EasyFraudError::InvalidFraudProof)
+        }
+        Err(e) if proof.kind == FraudKind::InvalidTransaction && rejects_transaction(&e) => {
+            return Ok(FraudVerdict::InvalidTransaction { code: e.code() })
+        }
+        Err(e) => return Err(e),
+    };
+    if proof.kind == FraudKind::InvalidTransaction {
+        return Ok(FraudVerdict::Honest);
+    }
+    let computed_isr = transfer.root.ok_or(EasyFraudError::NoRoot)?;
+    if computed_isr == proof.claimed_isr {
+        return Ok(FraudVerdict::Honest);
+    }
+    Ok(FraudVerdict::WrongIsr { computed_isr })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EasyFraudError> {
+        if self.0.len() < len {
+            return Err(EasyFraudError::InvalidFraudProof);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, EasyFraudError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], EasyFraudError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
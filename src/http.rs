@@ -0,0 +1,98 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::errors::EasyFraudError;
+
+// just enough http/1.1 to talk json-rpc and hand off fraud reports. plain http,
+// one connection per request, anything remote is expected behind a tls terminating proxy.
+
+// a node that's down should fail fast, even when the request itself may take a while
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// (host:port, path) of an http:// url
+pub fn split_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (address, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if address.is_empty() {
+        return None;
+    }
+    Some((address.into(), path.into()))
+}
+
+// POST body and return the body of the 200 that comes back.
+// a celestia node's blob.Submit waits for inclusion, so timeout has to allow for a few blocks.
+pub fn post(address: &str, path: &str, content_type: &str, auth_token: Option<&str>, body: &[u8], timeout: Duration) -> Result<Vec<u8>, EasyFraudError> {
+    let connection = |e: std::io::Error| EasyFraudError::Http(e.to_string());
+    let mut stream = connect(address, timeout.min(CONNECT_TIMEOUT))?;
+    stream.set_read_timeout(Some(timeout)).map_err(connection)?;
+    stream.set_write_timeout(Some(timeout)).map_err(connection)?;
+    let mut req = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        path,
+        address,
+        content_type,
+        body.len(),
+    );
+    if let Some(token) = auth_token {
+        req.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    req.push_str("\r\n");
+    stream.write_all(&[req.as_bytes(), body].concat()).map_err(connection)?;
+    let mut rsp = vec![];
+    stream.read_to_end(&mut rsp).map_err(connection)?;
+    http_body(&rsp)
+}
+
+// the first of the addresses host:port resolves to that takes the connection within timeout
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream, EasyFraudError> {
+    let mut last = EasyFraudError::Http(format!("{} did not resolve", address));
+    for addr in address.to_socket_addrs().map_err(|e| EasyFraudError::Http(e.to_string()))? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = EasyFraudError::Http(e.to_string()),
+        }
+    }
+    Err(last)
+}
+
+// the body of a 200 response, as is or put back together from chunks
+fn http_body(rsp: &[u8]) -> Result<Vec<u8>, EasyFraudError> {
+    let malformed = || EasyFraudError::Http("malformed response".into());
+    let split = rsp.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&rsp[..split]).map_err(|_| malformed())?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(EasyFraudError::Http(format!("answered {}", status)));
+    }
+    let chunked = lines.any(|line| matches!(line.split_once(':'),
+        Some((name, value)) if name.eq_ignore_ascii_case("transfer-encoding") && value.trim().eq_ignore_ascii_case("chunked")));
+    let mut body = &rsp[split + 4..];
+    if !chunked {
+        // the connection is closed after the response, so the body runs to the end
+        return Ok(body.to_vec());
+    }
+    let mut data = vec![];
+    loop {
+        let line_end = body.windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(malformed)?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16).ok())
+            .ok_or_else(malformed)?;
+        if size == 0 {
+            return Ok(data);
+        }
+        let chunk = body.get(line_end + 2..line_end + 2 + size).ok_or_else(malformed)?;
+        data.extend_from_slice(chunk);
+        body = body.get(line_end + 4 + size..).ok_or_else(malformed)?;
+    }
+}
@@ -0,0 +1,284 @@
+use std::ops::Range;
+
+use celestia_types::{
+    nmt::{Namespace, NamespacedHash, NamespacedHashExt, Nmt, NS_SIZE},
+    Blob,
+    Commitment,
+};
+use nmt_rs::{
+    nmt_proof::NamespaceProof,
+    simple_merkle::proof::Proof,
+    NamespaceId,
+    NamespacedSha2Hasher,
+};
+use sha2::{Digest, Sha256};
+
+use crate::blob::{blob_shares, SHARE_SIZE};
+use crate::errors::EasyFraudError;
+
+// min namespace | max namespace | sha256
+pub const NAMESPACED_HASH_SIZE: usize = 2 * NS_SIZE + 32;
+// the namespace of parity shares, and of the padding after the last blob in a square
+const PARITY_NAMESPACE: [u8; NS_SIZE] = [0xFF; NS_SIZE];
+const TAIL_PADDING_NAMESPACE: [u8; NS_SIZE] = {
+    let mut namespace = [0xFF; NS_SIZE];
+    namespace[NS_SIZE - 1] = 0xFE;
+    namespace
+};
+
+// proof that a run of shares in one row of a da height's square is part of its data
+// root. the shares' nmt range proof goes up to their row root, and the row root's merkle
+// proof up to the data root, which is over every row root and then every column root of
+// the extended square. a blob's shares are back to back in the square, so the ones it
+// has in a row are one run, and one proof.
+#[derive(Debug, PartialEq)]
+pub struct ShareInclusion {
+    // width of the original data square, the extended one is twice as wide
+    pub square_width: u32,
+    pub row: u32,
+    // column of the first share in the run
+    pub column: u32,
+    pub shares: Vec<[u8; SHARE_SIZE]>,
+    pub nmt_siblings: Vec<Vec<u8>>,
+    pub row_root: Vec<u8>,
+    // sibling hashes from the row root up to the data root
+    pub aunts: Vec<[u8; 32]>,
+}
+
+impl ShareInclusion {
+    // where the first share is in the original square, counting row by row
+    pub fn first(&self) -> usize {
+        self.row as usize * self.square_width as usize + self.column as usize
+    }
+
+    // the share at index in the original square, if it's in the run
+    pub fn share(&self, index: usize) -> Option<&[u8; SHARE_SIZE]> {
+        index.checked_sub(self.first()).and_then(|i| self.shares.get(i))
+    }
+
+    pub fn verify(&self, namespace: Namespace, data_root: &[u8; 32]) -> Result<(), EasyFraudError> {
+        let width = self.square_width as usize;
+        let (row, column) = (self.row as usize, self.column as usize);
+        // only the rows and columns of the original square hold blobs
+        if !width.is_power_of_two() || row >= width || self.shares.is_empty() || column + self.shares.len() > width {
+            return Err(EasyFraudError::InvalidInclusion);
+        }
+
+        let root = NamespacedHash::from_raw(&self.row_root)
+            .map_err(|_| EasyFraudError::InvalidInclusion)?;
+        let siblings = self.nmt_siblings.iter()
+            .map(|sibling| NamespacedHash::from_raw(sibling))
+            .collect::<Result<Vec<NamespacedHash>, _>>()
+            .map_err(|_| EasyFraudError::InvalidInclusion)?;
+        let proof: NamespaceProof<NamespacedSha2Hasher<NS_SIZE>, NS_SIZE> = NamespaceProof::PresenceProof {
+            proof: Proof {
+                siblings,
+                range: self.column..self.column + self.shares.len() as u32,
+            },
+            ignore_max_ns: true,
+        };
+        let shares = self.shares.iter().map(|share| &share[..]).collect::<Vec<&[u8]>>();
+        proof.verify_range(&root, &shares, *namespace)
+            .map_err(|_| EasyFraudError::InvalidInclusion)?;
+
+        match root_from_aunts(row, 4 * width, leaf_hash(&self.row_root), &self.aunts) {
+            Some(root) if root == *data_root => Ok(()),
+            _ => Err(EasyFraudError::InvalidInclusion),
+        }
+    }
+}
+
+// the roots a da height's header commits to, one per row of the extended square
+// and one per column
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRoots {
+    pub row_roots: Vec<Vec<u8>>,
+    pub column_roots: Vec<Vec<u8>>,
+}
+
+impl DataRoots {
+    // merkle root over the row roots and then the column roots
+    pub fn data_root(&self) -> [u8; 32] {
+        merkle_root(&self.all())
+    }
+
+    // siblings of the root of row on the way up to the data root
+    pub fn row_aunts(&self, row: usize) -> Vec<[u8; 32]> {
+        merkle_aunts(&self.all(), row)
+    }
+
+    fn all(&self) -> Vec<Vec<u8>> {
+        [&self.row_roots[..], &self.column_roots[..]].concat()
+    }
+}
+
+// the original data square of a da height, laid out the way celestia lays out blobs:
+// sorted by namespace, each starting on a share of its own, and tail padding filling
+// up the smallest square, a power of two wide, that fits them. the stand-in da layers
+// build one from what was posted at a height, for roots and proofs to hand out.
+// it isn't erasure coded, every parity share is zeros in the parity namespace. proofs
+// against it check out the same way, the roots just aren't the ones celestia would
+// come up with for the same blobs.
+pub struct DataSquare {
+    width: usize,
+    shares: Vec<[u8; SHARE_SIZE]>,
+    // the commitment of every blob and the shares it takes up
+    blobs: Vec<(Commitment, Range<usize>)>,
+}
+
+impl DataSquare {
+    pub fn new(blobs: &[Blob]) -> Self {
+        let mut sorted = blobs.iter().collect::<Vec<&Blob>>();
+        // stable, blobs in one namespace stay in the order they were posted
+        sorted.sort_by(|a, b| a.namespace.as_bytes().cmp(b.namespace.as_bytes()));
+        let mut shares = vec![];
+        let mut ranges = vec![];
+        for blob in sorted {
+            let start = shares.len();
+            shares.extend(blob_shares(blob).into_iter().map(|share| share.data));
+            ranges.push((blob.commitment, start..shares.len()));
+        }
+        let mut width = 1;
+        while width * width < shares.len() {
+            width *= 2;
+        }
+        let mut padding = [0; SHARE_SIZE];
+        padding[..NS_SIZE].copy_from_slice(&TAIL_PADDING_NAMESPACE);
+        // share version 0, a sequence of its own with no data
+        padding[NS_SIZE] = 1;
+        shares.resize(width * width, padding);
+        DataSquare {
+            width,
+            shares,
+            blobs: ranges,
+        }
+    }
+
+    pub fn roots(&self) -> DataRoots {
+        let width = self.width;
+        let column = |column: usize| (0..width)
+            .filter(move |_| column < width)
+            .map(move |row| &self.shares[row * width + column]);
+        DataRoots {
+            row_roots: (0..2 * width)
+                .map(|row| self.row_tree(row).root().to_array().to_vec())
+                .collect(),
+            column_roots: (0..2 * width)
+                .map(|i| extended_tree(column(i), width).root().to_array().to_vec())
+                .collect(),
+        }
+    }
+
+    // proof for share share_index of the blob with commitment, along with the rest
+    // of the blob's shares in its row. None if there's no such share.
+    pub fn inclusion(&self, commitment: &Commitment, share_index: usize) -> Option<ShareInclusion> {
+        let (_, blob) = self.blobs.iter().find(|(blob, _)| blob == commitment)?;
+        let index = blob.start + share_index;
+        if index >= blob.end {
+            return None;
+        }
+        let width = self.width;
+        let row = index / width;
+        let run = blob.start.max(row * width)..blob.end.min((row + 1) * width);
+        let columns = run.start - row * width..run.end - row * width;
+        let (_, proof) = self.row_tree(row).get_range_with_proof(columns.clone());
+        let roots = self.roots();
+        Some(ShareInclusion {
+            square_width: width as u32,
+            row: row as u32,
+            column: columns.start as u32,
+            shares: self.shares[run].to_vec(),
+            nmt_siblings: proof.siblings().iter()
+                .map(|sibling| sibling.to_array().to_vec())
+                .collect(),
+            row_root: roots.row_roots[row].clone(),
+            aunts: roots.row_aunts(row),
+        })
+    }
+
+    fn row_tree(&self, row: usize) -> Nmt {
+        let width = self.width;
+        let original = if row < width {
+            &self.shares[row * width..(row + 1) * width]
+        } else {
+            &[][..]
+        };
+        extended_tree(original.iter(), width)
+    }
+}
+
+// nmt over a row or column of the extended square: the shares it has in the original
+// square, then parity up to twice the width
+fn extended_tree<'a>(original: impl Iterator<Item = &'a [u8; SHARE_SIZE]>, width: usize) -> Nmt {
+    let mut tree = Nmt::new();
+    let mut leaves = 0;
+    for share in original {
+        // unwrap is safe, the square is sorted by namespace down its rows and columns alike
+        tree.push_leaf(&share[..], NamespaceId(share[..NS_SIZE].try_into().unwrap())).unwrap();
+        leaves += 1;
+    }
+    for _ in leaves..2 * width {
+        // unwrap is safe, nothing sorts after the parity namespace
+        tree.push_leaf(&[0; SHARE_SIZE], NamespaceId(PARITY_NAMESPACE)).unwrap();
+    }
+    tree
+}
+
+// rfc 6962 merkle tree over sha256, the one tendermint and celestia hash byte slices with.
+// a blob commits to its block's pairs with it too.
+pub fn leaf_hash(leaf: &[u8]) -> [u8; 32] {
+    Sha256::new().chain_update([0u8]).chain_update(leaf).finalize().into()
+}
+
+fn inner_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new().chain_update([1u8]).chain_update(left).chain_update(right).finalize().into()
+}
+
+// largest power of two below n
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+pub fn merkle_root(items: &[Vec<u8>]) -> [u8; 32] {
+    match items.len() {
+        0 => Sha256::digest(b"").into(),
+        1 => leaf_hash(&items[0]),
+        n => {
+            let k = split_point(n);
+            inner_hash(&merkle_root(&items[..k]), &merkle_root(&items[k..]))
+        }
+    }
+}
+
+// siblings of items[index] from the bottom up
+pub fn merkle_aunts(items: &[Vec<u8>], index: usize) -> Vec<[u8; 32]> {
+    if items.len() <= 1 {
+        return vec![];
+    }
+    let k = split_point(items.len());
+    let (mut aunts, sibling) = if index < k {
+        (merkle_aunts(&items[..k], index), merkle_root(&items[k..]))
+    } else {
+        (merkle_aunts(&items[k..], index - k), merkle_root(&items[..k]))
+    };
+    aunts.push(sibling);
+    aunts
+}
+
+pub fn root_from_aunts(index: usize, total: usize, leaf: [u8; 32], aunts: &[[u8; 32]]) -> Option<[u8; 32]> {
+    if index >= total {
+        return None;
+    }
+    match (total, aunts.split_last()) {
+        (1, None) => Some(leaf),
+        (1, Some(_)) | (_, None) => None,
+        (_, Some((sibling, rest))) => {
+            let k = split_point(total);
+            if index < k {
+                Some(inner_hash(&root_from_aunts(index, k, leaf, rest)?, sibling))
+            } else {
+                Some(inner_hash(sibling, &root_from_aunts(index - k, total - k, leaf, rest)?))
+            }
+        }
+    }
+}
@@ -8,23 +8,117 @@ mod block;
 use block::{*};
 mod utils;
 use utils::*;
+mod server;
+use server::{AbciService, Server};
+mod config;
+use config::Config;
+mod store;
+mod snapshot;
+mod db;
+mod fraud;
+mod inclusion;
+mod blob;
+mod da;
+mod celestia;
+mod http;
+mod derive;
+mod watchtower;
+
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 
 use celestia_types::{Commitment};
 use celestia_types::nmt::{Namespace};
+use log::{error, info};
 
-fn main() {
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let config = match Config::from_args(std::env::args()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: easyfraud [--address <tcp://host:port|unix:///path>] [--chain-id <id>] [--retain-blocks <n>] [--home <dir>] [--snapshot-interval <n>] [--snapshot-keep-recent <n>] [--namespace <hex>] [--da <memory|dir:path|celestia:http://host:port>] [--da-auth-token <token>] [--sequencer-key <file>] [--sequencer <hex>] [--derive | --watch <file:dir|http://host:port/path|rollup:http://host:port>] [--genesis <file>] [--validators <file>] [--da-start-height <n>]");
+            std::process::exit(2);
+        }
+    };
+    let mut state = match State::open(config.chain_id.clone(), &config.home) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("could not open state at {}: {}", config.home, e);
+            std::process::exit(1);
+        }
+    };
+    state.retain_blocks = config.retain_blocks;
+    state.snapshot_interval = config.snapshot_interval;
+    state.snapshot_keep_recent = config.snapshot_keep_recent;
+    state.namespace = config.namespace;
+    if config.derive || config.watch.is_some() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let signal = match server::shutdown_signal() {
+            Ok(signal) => signal,
+            Err(e) => {
+                error!("could not listen for signals: {}", e);
+                std::process::exit(1);
+            }
+        };
+        // blocks come from the da layer here, they aren't published back to it
+        let mut task = tokio::task::spawn_blocking({
+            let stop = stop.clone();
+            move || match config.watch {
+                Some(_) => watchtower::run(state, config, &stop),
+                None => derive::run(state, config, &stop),
+            }
+        });
+        let result = tokio::select! {
+            result = &mut task => result,
+            _ = signal => {
+                // the step in progress finishes, so what's stored is a whole da height
+                info!("shutting down after the current step");
+                stop.store(true, Ordering::Relaxed);
+                task.await
+            }
+        };
+        match result {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => error!("stopped: {}", e),
+            Err(e) => error!("stopped: {}", e),
+        }
+        std::process::exit(1);
+    }
+    if let Some(spec) = &config.da {
+        state.da = match da::open(spec, config.da_auth_token.clone()) {
+            Ok(da) => Some(da),
+            Err(e) => {
+                error!("could not open data availability layer {}: {}", spec, e);
+                std::process::exit(1);
+            }
+        };
+        // followers skip blocks that aren't signed, there's no publishing without a key
+        state.sequencer_key = match config.sequencer_key() {
+            Ok(key) => Some(key),
+            Err(e) => {
+                error!("could not read sequencer key: {}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+    let server = Server::new(AbciService::new(state));
+    if let Err(e) = server.listen(&config.address).await {
+        error!("server error: {}", e);
+        std::process::exit(1);
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use std::fmt;
+    use std::{collections::BTreeMap, fmt};
 
-    use crate::{state::AccountBalancePair, transaction::SignedTransaction};
+    use crate::{blob::{blob_shares, block_to_blob, decode_block, decode_signed_block, encode_block, pair_location}, da::{DataAvailability, DirectoryDA, MemoryDA}, derive::{apply_block, Derivation}, watchtower::{Sink, Watchtower}, fraud::{verify_fraud_proof, FraudKind, FraudProof, FraudVerdict}, inclusion::{merkle_aunts, DataRoots, DataSquare, ShareInclusion}, snapshot::{entries_from_bytes, Snapshot}, state::{validator_address, AccountBalancePair, VALIDATORS_KEY}, store::BlockStore, transaction::SignedTransaction};
 
     use super::*;
 
-    use monotree::Monotree;
+    use celestia_types::Blob;
     use rand::{rngs::OsRng, Rng};
     use ed25519_dalek::{
         VerifyingKey,
@@ -35,15 +129,94 @@ mod tests {
     };
     use tendermint::v0_38::abci::{
         request::{
+            ApplySnapshotChunk as RequestApplySnapshotChunk,
+            CheckTx as RequestCheckTx,
+            Echo as RequestEcho,
+            FinalizeBlock as RequestFinalizeBlock,
+            Info as RequestInfo,
             InitChain as RequestInitChain,
-        }
+            LoadSnapshotChunk as RequestLoadSnapshotChunk,
+            OfferSnapshot as RequestOfferSnapshot,
+            PrepareProposal as RequestPrepareProposal,
+            Query as RequestQuery,
+            ProcessProposal as RequestProcessProposal,
+        },
+        response::{
+            ApplySnapshotChunkResult,
+            OfferSnapshot as ResponseOfferSnapshot,
+        },
+        Request,
+        Response,
     };
+    use tendermint::abci::{
+        request::CheckTxKind,
+        types::CommitInfo,
+        Event,
+    };
+
+    // what the sequencer in every test signs its blocks with
+    fn sequencer_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    // a transfer signed by from for mychain
+    fn transfer(from: &SigningKey, to: [u8; 32], amount: u64, nonce: u64, fee: u64) -> SignedTransaction {
+        Transaction {
+            sender_pubkey: from.verifying_key().to_bytes(),
+            recipient_pubkey: to,
+            amount,
+            nonce,
+            fee,
+        }.sign("mychain", from)
+    }
+
+    fn genesis_request(whale: &SigningKey) -> RequestInitChain {
+        let genesis_account = AccountBalancePair {
+            pubkey: whale.verifying_key().to_bytes(),
+            balance: 1000000000,
+        };
+        let mut init_chain = RequestInitChain::default();
+        init_chain.chain_id = "mychain".into();
+        init_chain.app_state_bytes = genesis_account.serialize().to_vec().try_into().unwrap();
+        init_chain
+    }
+
+    fn genesis_state(whale: &SigningKey) -> State {
+        let mut state = State::new("mychain");
+        state.init_chain(genesis_request(whale)).unwrap();
+        state
+    }
+
+    // a fresh chain and the whale its every coin starts out with
+    fn genesis() -> (SigningKey, State) {
+        let whale = SigningKey::generate(&mut OsRng);
+        let state = genesis_state(&whale);
+        (whale, state)
+    }
+
+    fn finalize_block_request(height: u32, txs: Vec<Vec<u8>>) -> RequestFinalizeBlock {
+        RequestFinalizeBlock {
+            txs: txs.into_iter().map(Into::into).collect(),
+            decided_last_commit: CommitInfo {
+                round: 0u8.into(),
+                votes: vec![],
+            },
+            misbehavior: vec![],
+            hash: tendermint::Hash::None,
+            height: height.into(),
+            time: tendermint::Time::unix_epoch(),
+            next_validators_hash: tendermint::Hash::None,
+            proposer_address: tendermint::account::Id::new([0; 20]),
+        }
+    }
 
     fn test_serialize_transaction() {
         let t = Transaction{
             sender_pubkey: [1; 32],
             recipient_pubkey: [2; 32],
             amount: 3000,
+            nonce: 4,
+            fee: 0,
         };
         println!("transaction: {:?}", t);
         let bytes = t.serialize();
@@ -57,16 +230,7 @@ mod tests {
     fn test_block() {
         let mut csprng = OsRng;
         
-        let mut state = State {
-            initialized: false,
-            chain_id: "mychain".into(),
-            tree: Monotree::default(),
-            root: None,
-            current_block: None,
-            height: 0,
-            volatile_root: None,
-            volatile_diffs: vec![],
-        };
+        let mut state = State::new("mychain");
 
         let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
         let genesis_account = AccountBalancePair {
@@ -85,14 +249,9 @@ mod tests {
         println!("balance {}", leaf_to_num(&balance.unwrap()));
 
         let recipients: Vec<SigningKey> = std::iter::repeat_with(|| SigningKey::generate(&mut csprng)).take(100).collect();
-        let key_bytes = genesis_whale.verifying_key().to_bytes();
-        let block_txns = recipients.iter().map(|r| {
-            Transaction {
-                sender_pubkey: key_bytes.clone(),
-                recipient_pubkey: r.verifying_key().to_bytes(),
-                amount: csprng.gen_range(1000..=3000),
-            }.sign(&genesis_whale).serialize()
-        }).collect::<Vec<[u8; 136]>>();
+        let block_txns = recipients.iter().enumerate().map(|(nonce, r)| {
+            transfer(&genesis_whale, r.verifying_key().to_bytes(), csprng.gen_range(1000..=3000), nonce as u64, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
         let incoming_block = IncomingBlock {
             signed_transactions: block_txns,
         };
@@ -100,26 +259,18 @@ mod tests {
         //println!("Last ISR: {:?}", outgoing_block.pairs.last().unwrap().1);
         //println!("block header: {:?}", outgoing_block.header.apphash.unwrap());
         //println!("outgoing block: {:?}", outgoing_block);
-        let blob = pairs_into_blob(outgoing_block.pairs);
         let namespace = Namespace::new(0, b"beemovie").unwrap();
-        let commitment = Commitment::from_shares(namespace, &blob).unwrap();
+        let blob = block_to_blob(namespace, "mychain", &sequencer_key(), 1, &outgoing_block).unwrap();
+        let commitment = Commitment::from_shares(namespace, &blob_shares(&blob)).unwrap();
         println!("Commitment: {:?}", commitment);
+        assert_eq!(commitment, blob.commitment);
     }
 
     #[test]
     fn test_revert() {
         let mut csprng = OsRng;
         
-        let mut state = State {
-            initialized: false,
-            chain_id: "mychain".into(),
-            tree: Monotree::default(),
-            root: None,
-            current_block: None,
-            height: 0,
-            volatile_root: None,
-            volatile_diffs: vec![],
-        };
+        let mut state = State::new("mychain");
 
         let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
         let genesis_account = AccountBalancePair {
@@ -138,12 +289,7 @@ mod tests {
         println!("balance {}", leaf_to_num(&balance.unwrap()));
 
         let recipients: Vec<SigningKey> = std::iter::repeat_with(|| SigningKey::generate(&mut csprng)).take(100).collect();
-        let key_bytes = genesis_whale.verifying_key().to_bytes();
-        let tx = Transaction {
-            sender_pubkey: key_bytes.clone(),
-            recipient_pubkey: recipients[0].verifying_key().to_bytes(),
-            amount: 3000,
-        }.sign(&genesis_whale);
+        let tx = transfer(&genesis_whale, recipients[0].verifying_key().to_bytes(), 3000, 0, 0);
 
         let old_state = state.root;
         state.verify_and_run_transaction(&tx).unwrap();
@@ -154,20 +300,65 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_chain_id_signatures() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let genesis_root = state.root;
+        let txn = Transaction {
+            sender_pubkey: genesis_whale.verifying_key().to_bytes(),
+            recipient_pubkey: SigningKey::generate(&mut csprng).verifying_key().to_bytes(),
+            amount: 1000,
+            nonce: 0,
+            fee: 0,
+        };
+
+        // the same transfer signed for a fork that shares the whale's key
+        let forked = txn.sign("otherchain", &genesis_whale);
+        assert!(forked.verify("otherchain").unwrap());
+        assert!(!forked.verify("mychain").unwrap());
+        assert!(matches!(state.verify_and_run_transaction(&forked), Err(EasyFraudError::InvalidSignature)));
+        assert_eq!(state.root, genesis_root);
+        let rsp = match state.call(Request::CheckTx(RequestCheckTx { tx: forked.to_envelope().into(), kind: CheckTxKind::New })).unwrap() {
+            Response::CheckTx(rsp) => rsp,
+            _ => panic!("expected CheckTx response"),
+        };
+        assert_eq!(rsp.code.value(), EasyFraudError::InvalidSignature.code());
+
+        // signed for this chain it goes through
+        let stx = txn.sign("mychain", &genesis_whale);
+        assert_eq!(stx.transaction_data, forked.transaction_data);
+        assert!(state.verify_and_run_transaction(&stx).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_batch_verification() {
+        let mut csprng = OsRng;
+        let key: SigningKey = SigningKey::generate(&mut csprng);
+        let other: SigningKey = SigningKey::generate(&mut csprng);
+        // a few batches' worth, with bad signatures of every sort in some of them
+        let mut stxs = (0..150).map(|nonce| transfer(&key, [2; 32], 1000, nonce, 0)).collect::<Vec<SignedTransaction>>();
+        stxs[5].signature[0] ^= 1;
+        stxs[70] = Transaction::deserialize(stxs[70].transaction_data).unwrap().sign("otherchain", &key);
+        stxs[71] = Transaction::deserialize(stxs[71].transaction_data).unwrap().sign("mychain", &other);
+        stxs[149].transaction_data[64] ^= 1;
+
+        let verified = transaction::verify_signatures("mychain", &stxs.iter().collect::<Vec<&SignedTransaction>>());
+        let bad = verified.iter().enumerate()
+            .filter(|(_, ok)| !**ok)
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        assert_eq!(bad, vec![5, 70, 71, 149]);
+        // the same answer one by one
+        assert!(stxs.iter().zip(&verified).all(|(stx, ok)| stx.verify("mychain").unwrap() == *ok));
+        assert!(transaction::verify_signatures("mychain", &[]).is_empty());
+    }
+
     #[test]
     fn test_txns_with_invalid() {
         let mut csprng = OsRng;
         
-        let mut state = State {
-            initialized: false,
-            chain_id: "mychain".into(),
-            tree: Monotree::default(),
-            root: None,
-            current_block: None,
-            height: 0,
-            volatile_root: None,
-            volatile_diffs: vec![],
-        };
+        let mut state = State::new("mychain");
 
         let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
         let genesis_account = AccountBalancePair {
@@ -186,21 +377,1232 @@ mod tests {
         println!("balance {}", leaf_to_num(&balance.unwrap()));
 
         let recipients: Vec<SigningKey> = std::iter::repeat_with(|| SigningKey::generate(&mut csprng)).take(100).collect();
-        let key_bytes = genesis_whale.verifying_key().to_bytes();
-        let mut block_txns = recipients.iter().map(|r| {
-            Transaction {
-                sender_pubkey: key_bytes.clone(),
-                recipient_pubkey: r.verifying_key().to_bytes(),
-                amount: csprng.gen_range(1000..=3000),
-            }.sign(&genesis_whale).serialize()
-        }).collect::<Vec<[u8; 136]>>();
+        let broken = [42, 69];
+        let mut block_txns = recipients.iter().enumerate().map(|(i, r)| {
+            // the broken ones never run, the one after each gets their nonce
+            let nonce = (i - broken.iter().filter(|b| **b < i).count()) as u64;
+            transfer(&genesis_whale, r.verifying_key().to_bytes(), csprng.gen_range(1000..=3000), nonce, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
         // break two transactions by changing a byte in the signature
-        block_txns[69][78] = 15;
-        block_txns[42][78] = 1;
+        block_txns[69][94] ^= 15;
+        block_txns[42][94] ^= 1;
         let incoming_block = IncomingBlock {
             signed_transactions: block_txns,
         };
         let outgoing_block = incoming_block.process(&mut state).unwrap();
         assert_eq!(outgoing_block.pairs.len(), incoming_block.signed_transactions.len() - 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_call_dispatch() {
+        let mut state = State::new("mychain");
+        assert!(matches!(state.call(Request::Flush).unwrap(), Response::Flush));
+        let echo = state.call(Request::Echo(RequestEcho { message: "hello".into() })).unwrap();
+        assert!(matches!(echo, Response::Echo(e) if e.message == "hello"));
+        assert!(matches!(state.call(Request::ListSnapshots).unwrap(), Response::ListSnapshots(_)));
+    }
+
+    fn process_proposal_request(height: u32, txs: Vec<Vec<u8>>) -> RequestProcessProposal {
+        RequestProcessProposal {
+            txs: txs.into_iter().map(Into::into).collect(),
+            proposed_last_commit: None,
+            misbehavior: vec![],
+            hash: tendermint::Hash::None,
+            height: height.into(),
+            time: tendermint::Time::unix_epoch(),
+            next_validators_hash: tendermint::Hash::None,
+            proposer_address: tendermint::account::Id::new([0; 20]),
+        }
+    }
+
+    #[test]
+    fn test_finalize_block() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+
+        let amounts = [1000, 2000, 2000000000];
+        let txs = amounts.iter().enumerate().map(|(nonce, amount)| {
+            let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), *amount, nonce as u64, 0).to_envelope();
+            // the ISR is recomputed on finalize, a placeholder is fine here
+            SignedTxnISRPair(stx, [0; 32]).serialize()
+        }).collect();
+
+        let rsp = match state.call(Request::FinalizeBlock(finalize_block_request(1, txs))).unwrap() {
+            Response::FinalizeBlock(rsp) => rsp,
+            _ => panic!("expected FinalizeBlock response"),
+        };
+        let codes = rsp.tx_results.iter().map(|r| r.code.value()).collect::<Vec<u32>>();
+        assert_eq!(codes, vec![0, 0, EasyFraudError::InsufficientBalance.code()]);
+        assert_eq!(rsp.tx_results[1].data.to_vec(), state.root.unwrap().to_vec());
+        assert_eq!(rsp.app_hash.as_bytes(), &state.root.unwrap()[..]);
+        assert_eq!(state.current_block.as_ref().unwrap().pairs.len(), 2);
+    }
+
+    #[test]
+    fn test_commit() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        state.retain_blocks = 2;
+        let genesis_root = state.root;
+
+        for height in 1..=3u32 {
+            let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, height as u64 - 1, 0).to_envelope();
+            let tx = SignedTxnISRPair(stx, [0; 32]).serialize();
+            state.call(Request::FinalizeBlock(finalize_block_request(height, vec![tx]))).unwrap();
+            let rsp = match state.call(Request::Commit).unwrap() {
+                Response::Commit(rsp) => rsp,
+                _ => panic!("expected Commit response"),
+            };
+            assert_eq!(state.height, height as u64);
+            assert_eq!(rsp.retain_height.value(), (height as u64).saturating_sub(2) + 1);
+        }
+
+        // committed blocks survive a revert
+        let committed_root = state.root;
+        assert_ne!(committed_root, genesis_root);
+        assert!(state.volatile_diffs.is_empty());
+        state.revert_volatile();
+        assert_eq!(state.root, committed_root);
+        assert_eq!(state.app_hashes.keys().copied().collect::<Vec<u64>>(), vec![2, 3]);
+        assert_eq!(state.app_hashes.get(&3), committed_root.as_ref());
+    }
+
+    #[test]
+    fn test_process_proposal() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let genesis_root = state.root;
+
+        let block_txns = (0..10).map(|nonce| {
+            transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), csprng.gen_range(1000..=3000), nonce, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
+        let outgoing_block = IncomingBlock {
+            signed_transactions: block_txns,
+        }.process(&mut state).unwrap();
+        state.revert_volatile();
+        let mut txs = outgoing_block.pairs.iter()
+            .map(|pair| pair.serialize())
+            .collect::<Vec<Vec<u8>>>();
+
+        state.process_proposal(process_proposal_request(1, txs.clone())).unwrap();
+        assert_eq!(state.root, genesis_root);
+
+        // claim a bogus root after the 5th transaction
+        txs[4][158] ^= 1;
+        assert!(matches!(
+            state.process_proposal(process_proposal_request(1, txs)),
+            Err(EasyFraudError::IsrMismatch)
+        ));
+        assert_eq!(state.root, genesis_root);
+    }
+
+    #[test]
+    fn test_prepare_proposal() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let txs = (0..3).map(|nonce| {
+            transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, nonce, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
+        let prepare = |state: &mut State, max_tx_bytes: i64| {
+            let req = RequestPrepareProposal {
+                max_tx_bytes,
+                txs: txs.iter().cloned().map(Into::into).collect(),
+                local_last_commit: None,
+                misbehavior: vec![],
+                height: 1u32.into(),
+                time: tendermint::Time::unix_epoch(),
+                next_validators_hash: tendermint::Hash::None,
+                proposer_address: tendermint::account::Id::new([0; 20]),
+            };
+            match state.call(Request::PrepareProposal(req)).unwrap() {
+                Response::PrepareProposal(rsp) => rsp.txs.iter().map(|tx| tx.to_vec()).collect::<Vec<Vec<u8>>>(),
+                _ => panic!("expected PrepareProposal response"),
+            }
+        };
+
+        // every transaction goes out with its ISR, a byte short of the last pair leaves it out
+        let pair_size = txs[0].len() as i64 + 32;
+        assert_eq!(prepare(&mut state, 3 * pair_size).len(), 3);
+        let proposal = prepare(&mut state, 3 * pair_size - 1);
+        assert_eq!(proposal.len(), 2);
+        assert!(proposal.iter().map(|tx| tx.len() as i64).sum::<i64>() <= 3 * pair_size - 1);
+        // what's left still checks out, it's the start of the same block
+        state.process_proposal(process_proposal_request(1, proposal)).unwrap();
+        assert!(prepare(&mut state, pair_size - 1).is_empty());
+    }
+
+    #[test]
+    fn test_check_tx() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let recipient = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+
+        let check = |state: &mut State, tx: Vec<u8>, kind: CheckTxKind| {
+            match state.call(Request::CheckTx(RequestCheckTx { tx: tx.into(), kind })).unwrap() {
+                Response::CheckTx(rsp) => rsp.code.value(),
+                _ => panic!("expected CheckTx response"),
+            }
+        };
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 600000000, 0, 0).to_envelope(), CheckTxKind::New), 0);
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 300000000, 1, 0).to_envelope(), CheckTxKind::New), 0);
+        // would overdraw once the two pending transfers land
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 200000000, 2, 0).to_envelope(), CheckTxKind::New), EasyFraudError::InsufficientBalance.code());
+        // and so would the fee on top of a transfer that fits by itself
+        let with_fee = transfer(&genesis_whale, recipient, 50000000, 2, 50000000).to_envelope();
+        assert_eq!(check(&mut state, with_fee, CheckTxKind::New), EasyFraudError::InsufficientBalance.code());
+        assert_eq!(check(&mut state, vec![7; 100], CheckTxKind::New), EasyFraudError::InvalidEnvelope.code());
+        assert_eq!(check(&mut state, [&[2, 0][..], &[94, 0, 0, 0], &[7; 94]].concat(), CheckTxKind::New), EasyFraudError::UnsupportedEnvelopeVersion(2).code());
+        assert_eq!(check(&mut state, vec![1, 9, 0, 0, 0, 0], CheckTxKind::New), EasyFraudError::UnknownTransactionKind(9).code());
+        // envelopes that are cut short, or say they're shorter than they are, don't panic
+        let envelope = transfer(&genesis_whale, recipient, 1000, 2, 0).to_envelope();
+        assert_eq!(check(&mut state, envelope[..3].to_vec(), CheckTxKind::New), EasyFraudError::InvalidEnvelope.code());
+        assert_eq!(check(&mut state, envelope[..100].to_vec(), CheckTxKind::New), EasyFraudError::InvalidEnvelope.code());
+        assert_eq!(check(&mut state, [&envelope[..], &[0]].concat(), CheckTxKind::New), EasyFraudError::InvalidEnvelope.code());
+        let mut short = envelope[..100].to_vec();
+        short[2..6].copy_from_slice(&94u32.to_le_bytes());
+        assert_eq!(check(&mut state, short, CheckTxKind::New), EasyFraudError::TransactionDeserializationError.code());
+        assert_eq!(check(&mut state, vec![7; 136], CheckTxKind::New), EasyFraudError::LegacyTransaction.code());
+        // a transfer from before fees, in an envelope
+        assert_eq!(check(&mut state, [&[1, 0][..], &[144, 0, 0, 0], &[7; 144]].concat(), CheckTxKind::New), EasyFraudError::LegacyTransaction.code());
+        let mut forged = transfer(&genesis_whale, recipient, 1000, 2, 0).to_envelope();
+        forged[100] ^= 1;
+        assert_eq!(check(&mut state, forged, CheckTxKind::New), EasyFraudError::InvalidSignature.code());
+        // the validators' leaf isn't an account anything can be paid into
+        let to_validators = transfer(&genesis_whale, VALIDATORS_KEY, 1000, 2, 0).to_envelope();
+        assert_eq!(check(&mut state, to_validators, CheckTxKind::New), EasyFraudError::ReservedAccount.code());
+        // a replay, and a nonce too far ahead, are turned away
+        let invalid_nonce = EasyFraudError::InvalidNonce { expected: 2, actual: 0 }.code();
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 600000000, 0, 0).to_envelope(), CheckTxKind::New), invalid_nonce);
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 1000, 3, 0).to_envelope(), CheckTxKind::New), invalid_nonce);
+
+        // nothing landed, so after a commit the overlay starts over
+        state.call(Request::Commit).unwrap();
+        assert_eq!(check(&mut state, transfer(&genesis_whale, recipient, 200000000, 0, 0).to_envelope(), CheckTxKind::Recheck), 0);
+    }
+
+    #[test]
+    fn test_restart_resumes_from_disk() {
+        let mut csprng = OsRng;
+        let home = std::env::temp_dir().join(format!("easyfraud-test-{}", csprng.gen::<u64>()));
+        let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
+
+        let mut state = State::open("mychain", &home).unwrap();
+        state.call(Request::InitChain(genesis_request(&genesis_whale))).unwrap();
+        let recipient = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+        for height in 1..=2u32 {
+            let stx = transfer(&genesis_whale, recipient, 1000, height as u64 - 1, 0).to_envelope();
+            let tx = SignedTxnISRPair(stx, [0; 32]).serialize();
+            state.call(Request::FinalizeBlock(finalize_block_request(height, vec![tx]))).unwrap();
+            state.call(Request::Commit).unwrap();
+        }
+        // a block that was finalized but never committed never reaches the disk
+        let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, 2, 0).to_envelope();
+        state.call(Request::FinalizeBlock(finalize_block_request(3, vec![SignedTxnISRPair(stx, [0; 32]).serialize()]))).unwrap();
+        let committed_root = state.app_hashes[&2];
+        drop(state);
+
+        let mut restarted = State::open("mychain", &home).unwrap();
+        assert_eq!(restarted.height, 2);
+        assert_eq!(restarted.root, Some(committed_root));
+        let info = match restarted.call(Request::Info(RequestInfo::default())).unwrap() {
+            Response::Info(info) => info,
+            _ => panic!("expected Info response"),
+        };
+        assert_eq!(info.last_block_height.value(), 2);
+        assert_eq!(info.last_block_app_hash.as_bytes(), &committed_root[..]);
+        // balances come straight from the persisted tree
+        let balance = restarted.tree.get(restarted.root.as_ref(), &recipient).unwrap().unwrap();
+        assert_eq!(leaf_to_num(&balance), 2000);
+        assert_eq!(restarted.accounts.len(), 3);
+        drop(restarted);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn test_query_balance_proof() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let recipient = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+        let stx = transfer(&genesis_whale, recipient, 1000, 0, 0).to_envelope();
+        let tx = SignedTxnISRPair(stx, [0; 32]).serialize();
+        state.call(Request::FinalizeBlock(finalize_block_request(1, vec![tx]))).unwrap();
+        state.call(Request::Commit).unwrap();
+
+        let query_path = |state: &mut State, path: &str, pubkey: &[u8; 32], height: u32| {
+            let req = RequestQuery {
+                data: Default::default(),
+                path: format!("/{}/{}", path, to_hex(pubkey)),
+                height: height.into(),
+                prove: true,
+            };
+            match state.call(Request::Query(req)).unwrap() {
+                Response::Query(rsp) => rsp,
+                _ => panic!("expected Query response"),
+            }
+        };
+        let query = |state: &mut State, pubkey: &[u8; 32], height: u32| query_path(state, "balance", pubkey, height);
+
+        let rsp = query(&mut state, &recipient, 1);
+        assert_eq!(rsp.code.value(), 0);
+        assert_eq!(u64::from_le_bytes(rsp.value.to_vec().try_into().unwrap()), 1000);
+        let op = &rsp.proof.unwrap().ops[0];
+        let leaf: [u8; 32] = op.data[..32].try_into().unwrap();
+        let proof = proof_from_bytes(&op.data[32..]).unwrap();
+        assert!(verify_leaf_proof(&state.app_hashes[&1], &leaf, &proof));
+        assert!(!verify_leaf_proof(&state.app_hashes[&0], &leaf, &proof));
+
+        // height 0 is the latest commit, and this account was never funded
+        let stranger = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+        let rsp = query(&mut state, &stranger, 0);
+        assert_eq!(rsp.code.value(), EasyFraudError::AccountNotFound.code());
+        assert_ne!(rsp.code.value(), 0);
+        assert!(rsp.value.is_empty());
+        assert!(rsp.proof.is_none());
+        let rsp = query(&mut state, &recipient, 5);
+        assert_eq!(rsp.code.value(), EasyFraudError::UnknownHeight(5).code());
+
+        // the whale's next transfer needs nonce 1, receiving left the recipient's alone
+        let nonce = |state: &mut State, pubkey: &[u8; 32]| {
+            let rsp = query_path(state, "nonce", pubkey, 0);
+            u64::from_le_bytes(rsp.value.to_vec().try_into().unwrap())
+        };
+        assert_eq!(nonce(&mut state, &genesis_whale.verifying_key().to_bytes()), 1);
+        assert_eq!(nonce(&mut state, &recipient), 0);
+    }
+
+    #[test]
+    fn test_state_sync_snapshot() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        state.snapshot_interval = 2;
+        for height in 1..=2u32 {
+            let txs = (0..20).map(|i| {
+                let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, (height as u64 - 1) * 20 + i, 0).to_envelope();
+                SignedTxnISRPair(stx, [0; 32]).serialize()
+            }).collect();
+            state.call(Request::FinalizeBlock(finalize_block_request(height, txs))).unwrap();
+            state.call(Request::Commit).unwrap();
+        }
+
+        let snapshot = match state.call(Request::ListSnapshots).unwrap() {
+            Response::ListSnapshots(rsp) => rsp.snapshots[0].clone(),
+            _ => panic!("expected ListSnapshots response"),
+        };
+        assert_eq!(snapshot.height.value(), 2);
+
+        let chunks = (0..snapshot.chunks).map(|index| match state.call(Request::LoadSnapshotChunk(RequestLoadSnapshotChunk {
+            height: snapshot.height,
+            format: snapshot.format,
+            chunk: index,
+        })).unwrap() {
+            Response::LoadSnapshotChunk(rsp) => rsp.chunk.to_vec(),
+            _ => panic!("expected LoadSnapshotChunk response"),
+        }).collect::<Vec<Vec<u8>>>();
+        let apply = |fresh: &mut State, index: u32, chunk: Vec<u8>| match fresh.call(Request::ApplySnapshotChunk(RequestApplySnapshotChunk {
+            index,
+            chunk: chunk.into(),
+            sender: "peer".into(),
+        })).unwrap() {
+            Response::ApplySnapshotChunk(rsp) => rsp.result,
+            _ => panic!("expected ApplySnapshotChunk response"),
+        };
+        let offer = |fresh: &mut State, snapshot: &tendermint::abci::types::Snapshot| fresh.call(Request::OfferSnapshot(RequestOfferSnapshot {
+            snapshot: snapshot.clone(),
+            app_hash: state.app_hash().unwrap(),
+        })).unwrap();
+
+        let mut fresh = State::new("mychain");
+        assert!(matches!(offer(&mut fresh, &snapshot), Response::OfferSnapshot(ResponseOfferSnapshot::Accept)));
+        for (index, chunk) in chunks.iter().enumerate() {
+            // a tampered chunk gets refetched from someone else
+            let mut tampered = chunk.clone();
+            tampered[40] ^= 1;
+            assert_eq!(apply(&mut fresh, index as u32, tampered), ApplySnapshotChunkResult::Retry);
+            assert_eq!(apply(&mut fresh, index as u32, chunk.clone()), ApplySnapshotChunkResult::Accept);
+        }
+
+        assert_eq!(fresh.height, 2);
+        assert_eq!(fresh.root, state.root);
+        // the whale, the 40 recipients and the validators
+        assert_eq!(fresh.accounts.len(), 42);
+
+        // the validators in the metadata aren't taken on their word, the app hash commits to them
+        let entries = entries_from_bytes(&chunks.concat()).unwrap();
+        let forged = Snapshot::new(2, state.root.unwrap(), &entries, &BTreeMap::from([([1; 20], [2; 32])]));
+        let mut offered = snapshot.clone();
+        offered.metadata = forged.metadata.into();
+        offered.hash = forged.hash.to_vec().into();
+        let mut fresh = State::new("mychain");
+        assert!(matches!(offer(&mut fresh, &offered), Response::OfferSnapshot(ResponseOfferSnapshot::Accept)));
+        let results = chunks.iter().enumerate()
+            .map(|(index, chunk)| apply(&mut fresh, index as u32, chunk.clone()))
+            .collect::<Vec<ApplySnapshotChunkResult>>();
+        assert_eq!(results.last(), Some(&ApplySnapshotChunkResult::RejectSnapshot));
+        assert!(!fresh.initialized);
+    }
+
+    // post a block at rollup height as one blob signed by the sequencer, returns the da
+    // height and its commitment
+    fn publish_block(da: &mut dyn DataAvailability, namespace: Namespace, chain_id: &str, sequencer: &SigningKey, height: u64, block: &OutgoingBlock) -> Result<(u64, Commitment), EasyFraudError> {
+        let blob = block_to_blob(namespace, chain_id, sequencer, height, block)?;
+        let commitment = blob.commitment;
+        let da_height = da.submit(&[blob])?;
+        Ok((da_height, commitment))
+    }
+
+    // a block posted as the only blob in a da height's square, the way the sequencer would
+    struct Published {
+        namespace: Namespace,
+        blob: Blob,
+        square: DataSquare,
+    }
+
+    impl Published {
+        fn data_root(&self) -> [u8; 32] {
+            self.square.roots().data_root()
+        }
+
+        fn prove(&self, state: &mut State, block: &OutgoingBlock, index: usize) -> Result<FraudProof, EasyFraudError> {
+            let prove = |share| self.square.inclusion(&self.blob.commitment, share).ok_or(EasyFraudError::InvalidInclusion);
+            FraudProof::generate(state, block, index, &blob_shares(&self.blob), prove)
+        }
+    }
+
+    fn publish(block: &OutgoingBlock) -> Published {
+        let namespace = Namespace::new(0, b"beemovie").unwrap();
+        let blob = block_to_blob(namespace, "mychain", &sequencer_key(), 1, block).unwrap();
+        Published {
+            namespace,
+            square: DataSquare::new(&[blob.clone()]),
+            blob,
+        }
+    }
+
+    #[test]
+    fn test_fraud_proof_generation() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let genesis_root = state.root;
+
+        let block_txns = (0..5).map(|nonce| {
+            transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, nonce, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
+        let mut block = IncomingBlock {
+            signed_transactions: block_txns,
+        }.process(&mut state).unwrap();
+        state.revert_volatile();
+        // the proposer lies about the third ISR
+        block.pairs[2].1 = [7; 32];
+        let published = publish(&block);
+
+        let proof = published.prove(&mut state, &block, 2).unwrap();
+        assert_eq!(proof.kind, FraudKind::WrongIsr);
+        assert_eq!(proof.index, 2);
+        assert_eq!(proof.transaction, block.pairs[2].0);
+        assert_eq!(proof.prev_isr, block.pairs[1].1);
+        assert_eq!(proof.claimed_isr, [7; 32]);
+        assert!(!proof.witness.is_empty());
+        assert_eq!(FraudProof::deserialize(&proof.serialize()).unwrap(), proof);
+        let bytes = proof.serialize();
+        assert!(FraudProof::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(state.root, genesis_root);
+
+        let generate = |state: &mut State, index: usize| published.prove(state, &block, index);
+        assert!(matches!(generate(&mut state, 1), Err(EasyFraudError::NoFraud(1))));
+        assert!(matches!(generate(&mut state, 3), Err(EasyFraudError::EarlierFraud(2))));
+        assert!(matches!(generate(&mut state, 5), Err(EasyFraudError::InvalidPairIndex(5))));
+        assert_eq!(state.root, genesis_root);
+    }
+
+    #[test]
+    fn test_fraud_proof_verification() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let genesis_root = state.root.unwrap();
+        // pay an existing account and a fresh one, so both recipient cases are covered
+        let old_friend = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+        let recipients = [old_friend, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), old_friend];
+        let block_txns = recipients.iter().enumerate().map(|(nonce, recipient)| {
+            transfer(&genesis_whale, *recipient, 1000, nonce as u64, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
+        let mut block = IncomingBlock {
+            signed_transactions: block_txns,
+        }.process(&mut state).unwrap();
+        state.revert_volatile();
+        let honestly_published = publish(&block);
+
+        for index in [1, 2] {
+            let honest = block.pairs[index].1;
+            block.pairs[index].1 = [7; 32];
+            let published = publish(&block);
+            let mut proof = published.prove(&mut state, &block, index).unwrap();
+            block.pairs[index].1 = honest;
+            let verify = |proof: &FraudProof, data_root: &[u8; 32]| verify_fraud_proof(&proof.serialize(), "mychain", published.namespace, &sequencer_key().verifying_key(), data_root, 1, &genesis_root);
+            assert_eq!(verify(&proof, &published.data_root()).unwrap(), FraudVerdict::WrongIsr { computed_isr: honest });
+
+            // neither a proof with a node missing or tampered with holds up
+            let mut missing = FraudProof::deserialize(&proof.serialize()).unwrap();
+            missing.witness.pop();
+            assert!(matches!(verify(&missing, &published.data_root()), Err(EasyFraudError::InvalidFraudProof)));
+            let mut tampered = FraudProof::deserialize(&proof.serialize()).unwrap();
+            tampered.witness[0][0] ^= 1;
+            assert!(matches!(verify(&tampered, &published.data_root()), Err(EasyFraudError::InvalidFraudProof)));
+
+            // nor one about a block the sequencer didn't sign, or pairs that aren't the
+            // ones under the pairs root it signed
+            let impostor = SigningKey::generate(&mut csprng).verifying_key();
+            assert!(matches!(verify_fraud_proof(&proof.serialize(), "mychain", published.namespace, &impostor, &published.data_root(), 1, &genesis_root), Err(EasyFraudError::InvalidBlockSignature)));
+            let mut unproven = FraudProof::deserialize(&proof.serialize()).unwrap();
+            unproven.pair_aunts[0][0] ^= 1;
+            assert!(matches!(verify(&unproven, &published.data_root()), Err(EasyFraudError::PairNotPublished(i)) if i == index));
+            let mut unproven = FraudProof::deserialize(&proof.serialize()).unwrap();
+            unproven.prev_pair_aunts[0][0] ^= 1;
+            assert!(matches!(verify(&unproven, &published.data_root()), Err(EasyFraudError::PairNotPublished(i)) if i == index - 1));
+
+            // nor one against an ISR that was honestly published, the blob is the same size
+            // so it's in the same place. the merkle proof of the pair before runs past the
+            // made up ISR, it has to be the honest block's too.
+            proof.claimed_isr = honest;
+            proof.inclusions = proof.inclusions.iter()
+                .map(|inclusion| honestly_published.square.inclusion(&honestly_published.blob.commitment, inclusion.first()).unwrap())
+                .collect();
+            proof.prev_pair_aunts = merkle_aunts(&block.pairs.iter().map(SignedTxnISRPair::serialize).collect::<Vec<Vec<u8>>>(), index - 1);
+            assert_eq!(verify(&proof, &honestly_published.data_root()).unwrap(), FraudVerdict::Honest);
+        }
+    }
+
+    #[test]
+    fn test_invalid_transaction_fraud_proof() {
+        let mut csprng = OsRng;
+        let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
+        let stranger = SigningKey::generate(&mut csprng);
+        let mut state = genesis_state(&genesis_whale);
+        let genesis_root = state.root.unwrap();
+        let block = IncomingBlock {
+            signed_transactions: vec![transfer(&genesis_whale, SigningKey::generate(&mut OsRng).verifying_key().to_bytes(), 1000, 0, 0).to_envelope()],
+        }.process(&mut state).unwrap();
+        state.revert_volatile();
+        let honest = block.pairs[0].clone();
+
+        let mut bad_signature = transfer(&genesis_whale, SigningKey::generate(&mut OsRng).verifying_key().to_bytes(), 1000, 1, 0).to_envelope();
+        bad_signature[94] ^= 1;
+        let to_validators = transfer(&genesis_whale, VALIDATORS_KEY, 1000, 1, 0).to_envelope();
+        let cases = [
+            (bad_signature, EasyFraudError::InvalidSignature),
+            (transfer(&stranger, SigningKey::generate(&mut OsRng).verifying_key().to_bytes(), 1000, 0, 0).to_envelope(), EasyFraudError::SenderNotInitialized),
+            (transfer(&genesis_whale, SigningKey::generate(&mut OsRng).verifying_key().to_bytes(), 1000000000 - 1000, 1, 0).to_envelope(), EasyFraudError::InsufficientBalance),
+            // the honest transaction again, replayed right after itself
+            (honest.0.clone(), EasyFraudError::InvalidNonce { expected: 1, actual: 0 }),
+            (transfer(&genesis_whale, SigningKey::generate(&mut OsRng).verifying_key().to_bytes(), 1000, 2, 0).to_envelope(), EasyFraudError::InvalidNonce { expected: 1, actual: 2 }),
+            (to_validators, EasyFraudError::ReservedAccount),
+            // a kind of transaction nobody knows about
+            (vec![1, 9, 0, 0, 0, 0], EasyFraudError::UnknownTransactionKind(9)),
+        ];
+        for (stx, error) in cases {
+            // the proposer slipped the bad transaction in after an honest one,
+            // and claims the block ends on its made up ISR
+            let block = OutgoingBlock {
+                header: Header { apphash: Some([7; 32]), fee_recipient: None },
+                pairs: vec![honest.clone(), SignedTxnISRPair(stx, [7; 32])],
+            };
+            let published = publish(&block);
+            let proof = published.prove(&mut state, &block, 1).unwrap();
+            assert_eq!(proof.kind, FraudKind::InvalidTransaction);
+            assert_eq!(proof.prev_isr, honest.1);
+            assert_eq!(verify_fraud_proof(&proof.serialize(), "mychain", published.namespace, &sequencer_key().verifying_key(), &published.data_root(), 1, &genesis_root).unwrap(),
+                FraudVerdict::InvalidTransaction { code: error.code() });
+        }
+
+        // claiming a valid transaction is invalid proves nothing
+        let block = OutgoingBlock {
+            header: Header { apphash: Some([7; 32]), fee_recipient: None },
+            pairs: vec![SignedTxnISRPair(honest.0.clone(), [7; 32])],
+        };
+        let published = publish(&block);
+        let mut proof = published.prove(&mut state, &block, 0).unwrap();
+        proof.kind = FraudKind::InvalidTransaction;
+        assert_eq!(verify_fraud_proof(&proof.serialize(), "mychain", published.namespace, &sequencer_key().verifying_key(), &published.data_root(), 1, &genesis_root).unwrap(), FraudVerdict::Honest);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut csprng = OsRng;
+        let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
+        let whale = genesis_whale.verifying_key().to_bytes();
+        let validator: SigningKey = SigningKey::generate(&mut csprng);
+        let validator_key = validator.verifying_key().to_bytes();
+        let mut init_chain = genesis_request(&genesis_whale);
+        init_chain.validators = vec![tendermint::validator::Update {
+            pub_key: tendermint::PublicKey::from_raw_ed25519(&validator_key).unwrap(),
+            power: 1u32.into(),
+        }];
+        let mut state = State::new("mychain");
+        state.init_chain(init_chain).unwrap();
+        let genesis_root = state.root.unwrap();
+
+        let recipient = SigningKey::generate(&mut csprng).verifying_key().to_bytes();
+        let pair = |from: &SigningKey, amount: u64, nonce: u64, fee: u64| {
+            SignedTxnISRPair(transfer(from, recipient, amount, nonce, fee).to_envelope(), [0; 32]).serialize()
+        };
+        let balance = |state: &State, pubkey: &[u8; 32]| state.tree.get(state.root.as_ref(), pubkey).unwrap()
+            .map(|leaf| leaf_to_num(&leaf));
+        let proposed_by = |height: u32, txs: Vec<Vec<u8>>, proposer: [u8; 20]| {
+            let mut req = finalize_block_request(height, txs);
+            req.proposer_address = tendermint::account::Id::new(proposer);
+            req
+        };
+        let block_one = || vec![pair(&genesis_whale, 1000, 0, 10), pair(&genesis_whale, 1000, 1, 0), pair(&genesis_whale, 1000, 2, 25)];
+
+        // the proposer is paid every fee in its block, into the account of its consensus key
+        let rsp = match state.call(Request::FinalizeBlock(proposed_by(1, block_one(), validator_address(&validator_key)))).unwrap() {
+            Response::FinalizeBlock(rsp) => rsp,
+            _ => panic!("expected FinalizeBlock response"),
+        };
+        assert!(rsp.tx_results.iter().all(|r| r.code.is_ok()));
+        assert_eq!(rsp.tx_results[2].events, vec![Event::new("transfer", [
+            ("sender", to_hex(&whale), true),
+            ("recipient", to_hex(&recipient), true),
+            ("amount", "1000".to_string(), false),
+            ("fee", "25".to_string(), false),
+        ])]);
+        assert_eq!(balance(&state, &validator_key), Some(35));
+        assert_eq!(balance(&state, &whale), Some(1000000000 - 3035));
+        assert_eq!(balance(&state, &recipient), Some(3000));
+        let block = state.current_block.take().unwrap();
+        assert_eq!(block.header.fee_recipient, Some(validator_key));
+
+        // the fees are part of every ISR, a block claiming they went elsewhere doesn't check out
+        state.revert_volatile();
+        assert!(matches!(publish(&block).prove(&mut state, &block, 0), Err(EasyFraudError::NoFraud(0))));
+        let redirected = OutgoingBlock {
+            header: Header { apphash: block.header.apphash, fee_recipient: Some(recipient) },
+            pairs: block.pairs.clone(),
+        };
+        let published = publish(&redirected);
+        let proof = published.prove(&mut state, &redirected, 0).unwrap();
+        assert_eq!(proof.kind, FraudKind::WrongIsr);
+        assert!(matches!(verify_fraud_proof(&proof.serialize(), "mychain", published.namespace, &sequencer_key().verifying_key(), &published.data_root(), 1, &genesis_root), Ok(FraudVerdict::WrongIsr { .. })));
+
+        state.call(Request::FinalizeBlock(proposed_by(1, block_one(), validator_address(&validator_key)))).unwrap();
+        state.call(Request::Commit).unwrap();
+
+        // a fee the sender can't cover on top of the amount fails the transfer.
+        // the validator spends its fees with its consensus key, and with a proposer
+        // nobody knows the fee is still paid, but to no one.
+        let txs = vec![
+            pair(&genesis_whale, 1000, 3, 1000000000 - 4035),
+            pair(&validator, 20, 0, 5),
+            pair(&genesis_whale, 1000, 3, 5),
+        ];
+        let rsp = match state.call(Request::FinalizeBlock(proposed_by(2, txs, [0; 20]))).unwrap() {
+            Response::FinalizeBlock(rsp) => rsp,
+            _ => panic!("expected FinalizeBlock response"),
+        };
+        let codes = rsp.tx_results.iter().map(|r| r.code.value()).collect::<Vec<u32>>();
+        assert_eq!(codes, vec![EasyFraudError::InsufficientBalance.code(), 0, 0]);
+        assert_eq!(balance(&state, &validator_key), Some(10));
+        assert_eq!(balance(&state, &whale), Some(1000000000 - 4040));
+        assert_eq!(balance(&state, &recipient), Some(4020));
+        assert_eq!(state.current_block.as_ref().unwrap().header.fee_recipient, None);
+    }
+
+    #[test]
+    fn test_fraud_proof_inclusion() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut state) = genesis();
+        let genesis_root = state.root.unwrap();
+        // enough pairs for a few shares
+        let block_txns = (0..10).map(|nonce| {
+            transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, nonce, 0).to_envelope()
+        }).collect::<Vec<Vec<u8>>>();
+        let mut block = IncomingBlock {
+            signed_transactions: block_txns,
+        }.process(&mut state).unwrap();
+        state.revert_volatile();
+        block.pairs[3].1 = [7; 32];
+        let published = publish(&block);
+        let (namespace, data_root) = (published.namespace, published.data_root());
+
+        // the blob's 5 shares are in a 4 wide square, the ones with the header and pairs
+        // 2 and 3 are all in the first row and one proof
+        let proof = published.prove(&mut state, &block, 3).unwrap();
+        assert_eq!(proof.blob_start, 0);
+        assert_eq!(proof.inclusions.len(), 1);
+        assert_eq!((proof.inclusions[0].square_width, proof.inclusions[0].row, proof.inclusions[0].shares.len()), (4, 0, 4));
+        let bytes = proof.serialize();
+        assert!(matches!(verify_fraud_proof(&bytes, "mychain", namespace, &sequencer_key().verifying_key(), &data_root, 1, &genesis_root), Ok(FraudVerdict::WrongIsr { .. })));
+
+        // the blob has to be in the square the verifier has the data root of, in the rollup's namespace
+        let other_namespace = Namespace::new(0, b"othermovie").unwrap();
+        assert!(matches!(verify_fraud_proof(&bytes, "mychain", other_namespace, &sequencer_key().verifying_key(), &data_root, 1, &genesis_root), Err(EasyFraudError::InvalidInclusion)));
+        let filler = Blob::new(Namespace::new(0, b"aaa").unwrap(), vec![7; 2000]).unwrap();
+        let crowded = DataSquare::new(&[published.blob.clone(), filler]);
+        assert!(matches!(verify_fraud_proof(&bytes, "mychain", namespace, &sequencer_key().verifying_key(), &crowded.roots().data_root(), 1, &genesis_root), Err(EasyFraudError::InvalidInclusion)));
+
+        // wherever that is, here after another namespace's blob and across two rows
+        let prove = |share| crowded.inclusion(&published.blob.commitment, share).ok_or(EasyFraudError::InvalidInclusion);
+        let moved = FraudProof::generate(&mut state, &block, 3, &blob_shares(&published.blob), prove).unwrap();
+        assert_eq!(moved.blob_start, 5);
+        assert!(matches!(verify_fraud_proof(&moved.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &crowded.roots().data_root(), 1, &genesis_root), Ok(FraudVerdict::WrongIsr { .. })));
+        // and the proof has to say where, the shares don't read as a block from anywhere else
+        let mut shifted = FraudProof::deserialize(&moved.serialize()).unwrap();
+        shifted.blob_start = 6;
+        assert!(matches!(verify_fraud_proof(&shifted.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &crowded.roots().data_root(), 1, &genesis_root), Err(EasyFraudError::InvalidBlobHeader)));
+
+        // and has to hold the pairs the proof talks about
+        let mut unpublished = FraudProof::deserialize(&bytes).unwrap();
+        unpublished.prev_isr = [8; 32];
+        assert!(matches!(verify_fraud_proof(&unpublished.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &data_root, 1, &genesis_root), Err(EasyFraudError::PairNotPublished(2))));
+        let mut unpublished = FraudProof::deserialize(&bytes).unwrap();
+        // without the first share there's no header to count the pairs, nor offsets to find them with
+        unpublished.inclusions.remove(0);
+        assert!(matches!(verify_fraud_proof(&unpublished.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &data_root, 1, &genesis_root), Err(EasyFraudError::InvalidBlobHeader)));
+        let prove = |share| published.square.inclusion(&published.blob.commitment, share).ok_or(EasyFraudError::InvalidInclusion);
+        assert!(matches!(FraudProof::generate(&mut state, &block, 3, &blob_shares(&published.blob)[..1], prove), Err(EasyFraudError::PairNotPublished(2))));
+
+        // nor can it be about a pair past the ones the header counts, the offsets of those
+        // would be read out of the pairs themselves
+        for index in [10, u32::MAX] {
+            let mut out_of_range = FraudProof::deserialize(&bytes).unwrap();
+            out_of_range.index = index;
+            assert!(matches!(verify_fraud_proof(&out_of_range.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &data_root, 1, &genesis_root), Err(EasyFraudError::InvalidPairIndex(i)) if i == index as usize));
+        }
+
+        // and a pair has to start after the offset table, here pair 3's start points back into it
+        let mut data = published.blob.data.clone();
+        data[188..192].copy_from_slice(&180u32.to_le_bytes());
+        let overlapping = Blob::new(namespace, data).unwrap();
+        let shares = blob_shares(&overlapping);
+        assert!(pair_location(3, 10, |share| shares.get(share).map(|share| &share.data)).is_none());
+        let square = DataSquare::new(&[overlapping.clone()]);
+        let mut unpublished = FraudProof::deserialize(&bytes).unwrap();
+        unpublished.inclusions = vec![square.inclusion(&overlapping.commitment, 0).unwrap()];
+        assert!(matches!(verify_fraud_proof(&unpublished.serialize(), "mychain", namespace, &sequencer_key().verifying_key(), &square.roots().data_root(), 1, &genesis_root), Err(EasyFraudError::PairNotPublished(3))));
+    }
+
+    #[test]
+    fn test_blob_codec() {
+        let mut csprng = OsRng;
+        let pairs = (0..7).map(|i| {
+            let stx = transfer(&SigningKey::generate(&mut csprng), [2; 32], i, 0, 0).to_envelope();
+            SignedTxnISRPair(stx, [i as u8; 32])
+        }).collect::<Vec<SignedTxnISRPair>>();
+
+        let block = |count: usize| OutgoingBlock {
+            header: Header {
+                apphash: Some([9; 32]),
+                fee_recipient: None,
+            },
+            pairs: pairs[..count].to_vec(),
+        };
+
+        for count in 0..=pairs.len() {
+            let (height, decoded) = decode_block(&encode_block("mychain", &sequencer_key(), 5, &block(count)).unwrap()).unwrap();
+            assert_eq!(height, 5);
+            assert_eq!(decoded.header.apphash, Some([9; 32]));
+            assert_eq!(decoded.pairs.len(), count);
+            for (i, pair) in decoded.pairs.iter().enumerate() {
+                assert_eq!(pair.serialize(), pairs[i].serialize());
+            }
+        }
+        let mut no_apphash = block(1);
+        no_apphash.header.apphash = None;
+        assert!(matches!(encode_block("mychain", &sequencer_key(), 5, &no_apphash), Err(EasyFraudError::NullApphash)));
+
+        let data = encode_block("mychain", &sequencer_key(), 5, &block(pairs.len())).unwrap();
+        assert_eq!(data.len(), 1538);
+        assert!(matches!(decode_block(&data[..1000]), Err(EasyFraudError::TruncatedBlob { expected: 1538, actual: 1000 })));
+        assert!(matches!(decode_block(&data[..200]), Err(EasyFraudError::TruncatedBlob { expected: 208, actual: 200 })));
+        assert!(matches!(decode_block(&[]), Err(EasyFraudError::TruncatedBlob { expected: 176, actual: 0 })));
+        assert!(matches!(decode_block(&[&data[..], &[0]].concat()), Err(EasyFraudError::TrailingBlobData)));
+        let mut version = data.clone();
+        // version 4 blobs hold transactions without fees
+        version[0] = 4;
+        assert!(matches!(decode_block(&version), Err(EasyFraudError::UnsupportedBlobVersion(4))));
+        let mut header = data.clone();
+        header[2] = 1;
+        assert!(matches!(decode_block(&header), Err(EasyFraudError::InvalidBlobHeader)));
+        // pairs that aren't the ones under the pairs root
+        let mut swapped = data.clone();
+        swapped[1537] ^= 1;
+        assert!(matches!(decode_block(&swapped), Err(EasyFraudError::InvalidBlobHeader)));
+
+        // only the sequencer's signature, made for this chain, makes it a block
+        let sequencer = sequencer_key().verifying_key();
+        assert_eq!(decode_signed_block(&data, "mychain", &sequencer).unwrap().0, 5);
+        assert!(matches!(decode_signed_block(&data, "otherchain", &sequencer), Err(EasyFraudError::InvalidBlockSignature)));
+        let impostor = SigningKey::generate(&mut csprng);
+        assert!(matches!(decode_signed_block(&data, "mychain", &impostor.verifying_key()), Err(EasyFraudError::InvalidBlockSignature)));
+        let forged = encode_block("mychain", &impostor, 5, &block(pairs.len())).unwrap();
+        assert!(matches!(decode_signed_block(&forged, "mychain", &sequencer), Err(EasyFraudError::InvalidBlockSignature)));
+        let mut apphash = data.clone();
+        apphash[16] ^= 1;
+        assert!(matches!(decode_signed_block(&apphash, "mychain", &sequencer), Err(EasyFraudError::InvalidBlockSignature)));
+
+        // offsets that leave a gap before the first pair, or run backwards
+        let mut gap = data.clone();
+        gap[176] += 1;
+        assert!(matches!(decode_block(&gap), Err(EasyFraudError::InvalidBlobHeader)));
+        let mut backwards = data.clone();
+        backwards[184..188].copy_from_slice(&100u32.to_le_bytes());
+        assert!(matches!(decode_block(&backwards), Err(EasyFraudError::InvalidBlobHeader)));
+        // or an offset in the middle of a transaction
+        let mut split = data.clone();
+        split[180] += 1;
+        assert!(matches!(decode_block(&split), Err(EasyFraudError::DeserializePairsError)));
+    }
+
+    #[test]
+    fn test_blob_shares() {
+        let mut csprng = OsRng;
+        let namespace = Namespace::new(0, b"beemovie").unwrap();
+        let pairs = (0..7).map(|i| {
+            let stx = transfer(&SigningKey::generate(&mut csprng), [2; 32], i, 0, 0).to_envelope();
+            SignedTxnISRPair(stx, [i as u8; 32])
+        }).collect::<Vec<SignedTxnISRPair>>();
+
+        let block = |count: usize| OutgoingBlock {
+            header: Header {
+                apphash: Some([9; 32]),
+                fee_recipient: None,
+            },
+            pairs: pairs[..count].to_vec(),
+        };
+
+        for count in 0..=pairs.len() {
+            let blob = block_to_blob(namespace, "mychain", &sequencer_key(), 1, &block(count)).unwrap();
+            let shares = blob_shares(&blob);
+            // these are the shares celestia commits to
+            assert_eq!(Commitment::from_shares(namespace, &shares).unwrap(), blob.commitment);
+            // and pair_location points into them
+            for (i, pair) in pairs[..count].iter().enumerate() {
+                let bytes = pair_location(i, count, |share| shares.get(share).map(|share| &share.data)).unwrap().into_iter()
+                    .flat_map(|(share, range)| shares[share].data[range].to_vec())
+                    .collect::<Vec<u8>>();
+                assert_eq!(bytes, pair.serialize());
+            }
+        }
+
+        // 6 pairs are 1344 bytes of data: 478 in the first share, 482 in the second, 384 in
+        // the last and padding after that
+        let blob = block_to_blob(namespace, "mychain", &sequencer_key(), 1, &block(6)).unwrap();
+        let shares = blob_shares(&blob);
+        assert_eq!(shares.len(), 3);
+        assert_eq!(&shares[0].data[..29], namespace.as_bytes());
+        assert_eq!(shares[0].data[29], 1);
+        assert_eq!(shares[0].data[30..34], 1344u32.to_be_bytes());
+        assert_eq!(shares[1].data[29], 0);
+    }
+
+    #[test]
+    fn test_data_availability() {
+        let mut csprng = OsRng;
+        let dir = std::env::temp_dir().join(format!("easyfraud-test-{}", csprng.gen::<u64>()));
+        let namespace = Namespace::new(0, blob::DEFAULT_NAMESPACE).unwrap();
+        let backends: Vec<Box<dyn DataAvailability + Send>> = vec![
+            Box::new(MemoryDA::default()),
+            Box::new(DirectoryDA::open(&dir).unwrap()),
+        ];
+        for da in backends {
+            let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
+            let mut state = genesis_state(&genesis_whale);
+            state.da = Some(da);
+            state.sequencer_key = Some(sequencer_key());
+            let mut roots = vec![];
+            for height in 1..=3u32 {
+                let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, height as u64 - 1, 0).to_envelope();
+                let tx = SignedTxnISRPair(stx, [0; 32]).serialize();
+                state.call(Request::FinalizeBlock(finalize_block_request(height, vec![tx]))).unwrap();
+                state.call(Request::Commit).unwrap();
+                roots.push(state.root.unwrap());
+            }
+
+            // every committed block is one blob, one da height each
+            let da = state.da.as_ref().unwrap();
+            for height in 1..=3u64 {
+                let blobs = da.get_all(height, namespace).unwrap();
+                assert_eq!(blobs.len(), 1);
+                let (block_height, block) = decode_signed_block(&blobs[0].data, "mychain", &sequencer_key().verifying_key()).unwrap();
+                assert_eq!(block_height, height);
+                assert_eq!(block.header.apphash, Some(roots[height as usize - 1]));
+                assert_eq!(block.pairs.len(), 1);
+                assert_eq!(block.pairs[0].1, roots[height as usize - 1]);
+                let proof = da.get_proof(height, namespace, &blobs[0].commitment, 0).unwrap();
+                proof.verify(namespace, &da.roots(height).unwrap().data_root()).unwrap();
+            }
+            assert!(da.get_all(1, Namespace::new(0, b"othermovie").unwrap()).unwrap().is_empty());
+            assert!(matches!(da.get_all(4, namespace), Err(EasyFraudError::DAHeightNotReached(4))));
+            assert!(matches!(da.get_all(0, namespace), Err(EasyFraudError::DAHeightNotReached(0))));
+            assert!(matches!(da.get_proof(1, namespace, &Commitment([0; 32]), 0), Err(EasyFraudError::BlobNotFound(1))));
+        }
+
+        // the directory keeps its blobs, and where it's at, across a restart
+        let mut da = DirectoryDA::open(&dir).unwrap();
+        let (_, block) = decode_block(&da.get_all(3, namespace).unwrap()[0].data).unwrap();
+        let (da_height, commitment) = publish_block(&mut da, namespace, "mychain", &sequencer_key(), 4, &block).unwrap();
+        assert_eq!(da_height, 4);
+        assert_eq!(da.get_all(4, namespace).unwrap()[0].commitment, commitment);
+
+        // a reader opened before the writer's next submit still gets to see it
+        let reader = DirectoryDA::open(&dir).unwrap();
+        assert!(matches!(reader.get_all(5, namespace), Err(EasyFraudError::DAHeightNotReached(5))));
+        let (da_height, commitment) = publish_block(&mut da, namespace, "mychain", &sequencer_key(), 5, &block).unwrap();
+        assert_eq!(da_height, 5);
+        assert_eq!(reader.get_all(5, namespace).unwrap()[0].commitment, commitment);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // a da layer that holds every submit until it's let through, and fails them once
+    // there's nobody left to let them through
+    struct GatedDA {
+        gate: std::sync::mpsc::Receiver<()>,
+        da: MemoryDA,
+    }
+
+    impl DataAvailability for GatedDA {
+        fn submit(&mut self, blobs: &[Blob]) -> Result<u64, EasyFraudError> {
+            self.gate.recv().map_err(|_| EasyFraudError::Http("da layer is down".into()))?;
+            self.da.submit(blobs)
+        }
+
+        fn get_all(&self, height: u64, namespace: Namespace) -> Result<Vec<Blob>, EasyFraudError> {
+            self.da.get_all(height, namespace)
+        }
+
+        fn roots(&self, height: u64) -> Result<DataRoots, EasyFraudError> {
+            self.da.roots(height)
+        }
+
+        fn get_proof(&self, height: u64, namespace: Namespace, commitment: &Commitment, share_index: usize) -> Result<ShareInclusion, EasyFraudError> {
+            self.da.get_proof(height, namespace, commitment, share_index)
+        }
+    }
+
+    #[test]
+    fn test_commit_publishes_first() {
+        use tower::Service;
+
+        let mut csprng = OsRng;
+        let genesis_whale: SigningKey = SigningKey::generate(&mut csprng);
+        let mut state = genesis_state(&genesis_whale);
+        let (open, gate) = std::sync::mpsc::channel();
+        state.da = Some(Box::new(GatedDA { gate, da: MemoryDA::default() }));
+        state.sequencer_key = Some(sequencer_key());
+        let txs = (0..2).map(|nonce| {
+            let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, nonce, 0).to_envelope();
+            SignedTxnISRPair(stx, [0; 32]).serialize()
+        }).collect::<Vec<Vec<u8>>>();
+        let service = AbciService::new(state);
+        async fn height(mut service: AbciService) -> u64 {
+            let info = service.call(Request::Info(RequestInfo::default()));
+            match tokio::time::timeout(std::time::Duration::from_secs(5), info).await {
+                Ok(Ok(Response::Info(info))) => info.last_block_height.value(),
+                Err(_) => panic!("Info waited on the commit"),
+                _ => panic!("expected Info response"),
+            }
+        }
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            // the commit waits on the da layer, the other connections don't wait on it
+            service.clone().call(Request::FinalizeBlock(finalize_block_request(1, vec![txs[0].clone()]))).await.unwrap();
+            let commit = tokio::spawn(service.clone().call(Request::Commit));
+            assert_eq!(height(service.clone()).await, 0);
+            open.send(()).unwrap();
+            commit.await.unwrap().unwrap();
+            assert_eq!(height(service.clone()).await, 1);
+
+            // a block that doesn't make it to the da layer isn't committed
+            drop(open);
+            service.clone().call(Request::FinalizeBlock(finalize_block_request(2, vec![txs[1].clone()]))).await.unwrap();
+            assert!(matches!(service.clone().call(Request::Commit).await, Err(EasyFraudError::Http(_))));
+            assert_eq!(height(service.clone()).await, 1);
+        });
+    }
+
+    // a celestia node that answers every call with the next recorded response.
+    // calls without the right token get a 401 and don't use one up.
+    // hands back the json-rpc requests it was sent.
+    fn mock_celestia_node(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<serde_json::Value>>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let node = std::thread::spawn(move || {
+            let mut requests = vec![];
+            let mut responses = responses.into_iter().peekable();
+            while let Some(body) = responses.peek() {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = vec![];
+                let mut byte = [0; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap();
+                let len = head.lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap();
+                let mut req = vec![0; len];
+                stream.read_exact(&mut req).unwrap();
+                if !head.contains("\r\nAuthorization: Bearer testtoken\r\n") {
+                    stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                    continue;
+                }
+                requests.push(serde_json::from_slice(&req).unwrap());
+                // bigger bodies go out chunked, like go's http server sends them
+                let rsp = if body.len() > 512 {
+                    let chunks = body.as_bytes().chunks(256)
+                        .map(|chunk| format!("{:x}\r\n{}\r\n", chunk.len(), std::str::from_utf8(chunk).unwrap()))
+                        .collect::<String>();
+                    format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{}0\r\n\r\n", chunks)
+                } else {
+                    format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+                };
+                stream.write_all(rsp.as_bytes()).unwrap();
+                responses.next();
+            }
+            requests
+        });
+        (url, node)
+    }
+
+    #[test]
+    fn test_celestia_client() {
+        // celestia-node's responses to the calls below. testdata/celestia/generate.py records
+        // them from a node, or synthesizes them without one, so nothing about where the node
+        // put the blob is assumed here beyond what the responses say
+        let (url, node) = mock_celestia_node(vec![
+            include_str!("../testdata/celestia/blob_submit.json"),
+            include_str!("../testdata/celestia/blob_get_all.json"),
+            include_str!("../testdata/celestia/blob_get_all_not_found.json"),
+            include_str!("../testdata/celestia/header_get_by_height.json"),
+            include_str!("../testdata/celestia/blob_get_all_from_future.json"),
+            include_str!("../testdata/celestia/blob_get_proof.json"),
+            include_str!("../testdata/celestia/blob_get_all.json"),
+            include_str!("../testdata/celestia/header_get_by_height_submitted.json"),
+            include_str!("../testdata/celestia/header_get_by_height_submitted.json"),
+            include_str!("../testdata/celestia/blob_get_proof_not_found.json"),
+        ]);
+        let namespace = Namespace::new(0, blob::DEFAULT_NAMESPACE).unwrap();
+        let fixture = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap();
+        let submitted = fixture(include_str!("../testdata/celestia/blob_submit.json"))["result"].as_u64().unwrap();
+        let recorded = fixture(include_str!("../testdata/celestia/blob_get_all.json"));
+        let index = recorded["result"][0]["index"].as_u64().unwrap() as usize;
+        let width = fixture(include_str!("../testdata/celestia/header_get_by_height_submitted.json"))["result"]["dah"]["row_roots"].as_array().unwrap().len() / 2;
+
+        let unauthorized = da::open(&format!("celestia:{}", url), None).unwrap();
+        assert!(matches!(unauthorized.get_all(submitted, namespace), Err(EasyFraudError::Http(_))));
+
+        // the block in the fixture blob, signed for mychain
+        let block = OutgoingBlock {
+            header: Header {
+                apphash: Some([0x42; 32]),
+                fee_recipient: None,
+            },
+            pairs: (0..3u8).map(|i| SignedTxnISRPair(SignedTransaction::deserialize([i; 152]).unwrap().to_envelope(), [0x40 + i; 32])).collect(),
+        };
+        let mut da = da::open(&format!("celestia:{}", url), Some("testtoken".into())).unwrap();
+        let (height, commitment) = publish_block(da.as_mut(), namespace, "mychain", &sequencer_key(), 7, &block).unwrap();
+        assert_eq!(height, submitted);
+
+        let blobs = da.get_all(height, namespace).unwrap();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].commitment, commitment);
+        let (rollup_height, published) = decode_signed_block(&blobs[0].data, "mychain", &sequencer_key().verifying_key()).unwrap();
+        assert_eq!(rollup_height, 7);
+        assert_eq!(published.header.apphash, block.header.apphash);
+        assert_eq!(published.pairs.len(), 3);
+        assert_eq!(published.pairs[2].serialize(), block.pairs[2].serialize());
+
+        // not found with a header is an empty height, from the future isn't there yet
+        assert!(da.get_all(height - 1, namespace).unwrap().is_empty());
+        assert!(matches!(da.get_all(height + 1000, namespace), Err(EasyFraudError::DAHeightNotReached(h)) if h == height + 1000));
+
+        // the proof of the blob's second share has the blob's shares in that share's row.
+        // the index the node gave is into the extended square, which is twice as wide
+        let first = index / (2 * width) * width + index % (2 * width);
+        let row = (first + 1) / width;
+        let run = first.max(row * width)..(first + blob_shares(&blobs[0]).len()).min((row + 1) * width);
+        let proof = da.get_proof(height, namespace, &commitment, 1).unwrap();
+        assert_eq!((proof.square_width as usize, proof.row as usize, proof.column as usize), (width, row, run.start - row * width));
+        assert_eq!(proof.shares.len(), run.len());
+        proof.verify(namespace, &da.roots(height).unwrap().data_root()).unwrap();
+        assert!(matches!(da.get_proof(height, namespace, &Commitment([0; 32]), 0), Err(EasyFraudError::BlobNotFound(h)) if h == height));
+
+        // what went out is what the node recorded
+        let requests = node.join().unwrap();
+        let methods = requests.iter()
+            .map(|req| req["method"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(methods, vec![
+            "blob.Submit",
+            "blob.GetAll",
+            "blob.GetAll",
+            "header.GetByHeight",
+            "blob.GetAll",
+            "blob.GetProof",
+            "blob.GetAll",
+            "header.GetByHeight",
+            "header.GetByHeight",
+            "blob.GetProof",
+        ]);
+        let mut recorded_blob = recorded["result"][0].clone();
+        // where the blob ended up in the square is the node's business, it isn't submitted
+        recorded_blob.as_object_mut().unwrap().remove("index");
+        let recorded_namespace = recorded_blob["namespace"].clone();
+        assert_eq!(requests[0]["params"], serde_json::json!([[recorded_blob], -1.0]));
+        assert_eq!(requests[1]["params"], serde_json::json!([height, [recorded_namespace]]));
+        assert_eq!(requests[3]["params"], serde_json::json!([height - 1]));
+    }
+
+    #[test]
+    fn test_derivation() {
+        let mut csprng = OsRng;
+        let (genesis_whale, mut sequencer) = genesis();
+        sequencer.da = Some(Box::new(MemoryDA::default()));
+        sequencer.sequencer_key = Some(sequencer_key());
+        let pair = |amount: u64, nonce: u64, csprng: &mut OsRng| {
+            let stx = transfer(&genesis_whale, SigningKey::generate(csprng).verifying_key().to_bytes(), amount, nonce, 0);
+            SignedTxnISRPair(stx.to_envelope(), [0; 32]).serialize()
+        };
+        for height in 1..=3u32 {
+            // the zero amount transfer is rejected and never makes it into the block
+            let nonce = (height as u64 - 1) * 2;
+            let txs = vec![pair(1000, nonce, &mut csprng), pair(0, nonce + 1, &mut csprng), pair(2000, nonce + 1, &mut csprng)];
+            sequencer.call(Request::FinalizeBlock(finalize_block_request(height, txs))).unwrap();
+            sequencer.call(Request::Commit).unwrap();
+        }
+
+        // a follower that knows nothing but genesis ends up on the same state
+        let mut da = sequencer.da.take().unwrap();
+        da.submit(&[Blob::new(sequencer.namespace, b"not a block".to_vec()).unwrap()]).unwrap();
+        let mut derivation = Derivation::new(genesis_state(&genesis_whale), da, sequencer_key().verifying_key(), 1).unwrap();
+        assert_eq!(derivation.sync().unwrap(), 3);
+        assert_eq!(derivation.da_height, 5);
+        assert_eq!(derivation.state.root, sequencer.root);
+        assert_eq!(derivation.state.app_hashes, sequencer.app_hashes);
+
+        // the next block, kept back from the da layer to tamper with
+        let txs = vec![pair(1000, 6, &mut csprng), pair(3000, 7, &mut csprng)];
+        sequencer.call(Request::FinalizeBlock(finalize_block_request(4, txs))).unwrap();
+        sequencer.call(Request::Commit).unwrap();
+        let block = sequencer.current_block.take().unwrap();
+        let copy = |block: &OutgoingBlock| OutgoingBlock {
+            header: Header {
+                apphash: block.header.apphash,
+                fee_recipient: block.header.fee_recipient,
+            },
+            pairs: block.pairs.clone(),
+        };
+        let state = &mut derivation.state;
+        let root = state.root;
+        let mut wrong_isr = copy(&block);
+        wrong_isr.pairs[1].1 = [7; 32];
+        assert!(matches!(apply_block(state, 4, &wrong_isr), Err(EasyFraudError::FraudulentBlock { height: 4, index: 1 })));
+        let mut wrong_app_hash = copy(&block);
+        wrong_app_hash.header.apphash = Some([7; 32]);
+        assert!(matches!(apply_block(state, 4, &wrong_app_hash), Err(EasyFraudError::WrongAppHash(4))));
+        assert!(matches!(apply_block(state, 5, &block), Err(EasyFraudError::MissingBlock(4))));
+        assert!(matches!(apply_block(state, 3, &block), Err(EasyFraudError::ConflictingBlock(3))));
+        assert_eq!(state.root, root);
+        assert_eq!(state.height, 3);
+
+        // a block anyone but the sequencer signed is passed over, the real one goes through,
+        // a sequencer posting it again after a crash is fine
+        let namespace = derivation.state.namespace;
+        let post = |derivation: &mut Derivation, key: &SigningKey, height: u64, block: &OutgoingBlock| {
+            derivation.da.submit(&[block_to_blob(namespace, "mychain", key, height, block).unwrap()]).unwrap();
+        };
+        post(&mut derivation, &SigningKey::generate(&mut csprng), 4, &wrong_app_hash);
+        post(&mut derivation, &sequencer_key(), 4, &block);
+        post(&mut derivation, &sequencer_key(), 4, &block);
+        assert_eq!(derivation.sync().unwrap(), 4);
+        assert_eq!(derivation.state.root, sequencer.root);
+
+        // a fraudulent block is skipped, it doesn't hold up the real one after it
+        sequencer.call(Request::FinalizeBlock(finalize_block_request(5, vec![pair(1000, 8, &mut csprng)]))).unwrap();
+        sequencer.call(Request::Commit).unwrap();
+        let next = sequencer.current_block.take().unwrap();
+        let mut fraud = copy(&next);
+        fraud.pairs[0].1 = [7; 32];
+        post(&mut derivation, &sequencer_key(), 5, &fraud);
+        assert!(derivation.step().unwrap());
+        assert_eq!(derivation.state.height, 4);
+        post(&mut derivation, &sequencer_key(), 5, &next);
+        assert_eq!(derivation.sync().unwrap(), 5);
+        assert_eq!(derivation.state.root, sequencer.root);
+        assert_eq!(derivation.da_height, 10);
+    }
+
+    #[test]
+    fn test_watchtower() {
+        let mut csprng = OsRng;
+        let dir = std::env::temp_dir().join(format!("easyfraud-test-{}", csprng.gen::<u64>()));
+        let (home, da_dir, reports) = (dir.join("home"), dir.join("da"), dir.join("reports"));
+        let (genesis_whale, mut sequencer) = genesis();
+        sequencer.da = Some(Box::new(DirectoryDA::open(&da_dir).unwrap()));
+        sequencer.sequencer_key = Some(sequencer_key());
+        for height in 1..=3u32 {
+            if height == 3 {
+                // the last block is posted by hand, with its second ISR made up
+                sequencer.da = None;
+            }
+            let txs = (0..2).map(|i| {
+                let stx = transfer(&genesis_whale, SigningKey::generate(&mut csprng).verifying_key().to_bytes(), 1000, (height as u64 - 1) * 2 + i, 0).to_envelope();
+                SignedTxnISRPair(stx, [0; 32]).serialize()
+            }).collect();
+            sequencer.call(Request::FinalizeBlock(finalize_block_request(height, txs))).unwrap();
+            sequencer.call(Request::Commit).unwrap();
+        }
+        let mut block = sequencer.current_block.take().unwrap();
+        let honest = block.pairs[1].1;
+        block.pairs[1].1 = [7; 32];
+        let mut da = DirectoryDA::open(&da_dir).unwrap();
+        let (da_height, _) = publish_block(&mut da, sequencer.namespace, "mychain", &sequencer_key(), 3, &block).unwrap();
+        assert_eq!(da_height, 3);
+
+        let watch = || {
+            let mut state = State::open("mychain", &home).unwrap();
+            if !state.initialized {
+                state.init_chain(genesis_request(&genesis_whale)).unwrap();
+            }
+            let derivation = Derivation::new(state, Box::new(DirectoryDA::open(&da_dir).unwrap()), sequencer_key().verifying_key(), 1).unwrap();
+            Watchtower::new(derivation, Sink::open(&format!("file:{}", reports.display())).unwrap()).unwrap()
+        };
+        // the fraudulent block is reported and skipped, and the watchtower goes on past it
+        let mut watchtower = watch();
+        assert_eq!(watchtower.sync().unwrap(), 2);
+        assert_eq!(watchtower.derivation.da_height, 4);
+        assert!(watchtower.reported.contains(&3));
+
+        // the report says where the block is, and the proof in it checks out against the
+        // data root there
+        let report = std::fs::read(reports.join("3")).unwrap();
+        assert_eq!(report[..8], 3u64.to_le_bytes());
+        assert_eq!(report[8..16], 3u64.to_le_bytes());
+        let start_root = watchtower.derivation.state.app_hashes[&2];
+        let data_root = da.roots(3).unwrap().data_root();
+        assert!(matches!(verify_fraud_proof(&report[16..], "mychain", sequencer.namespace, &sequencer_key().verifying_key(), &data_root, 3, &start_root), Ok(FraudVerdict::WrongIsr { .. })));
+        // nor does it hold up against the root the next block starts from, the block's
+        // first pair doesn't start from there
+        let next_root = sequencer.app_hashes[&3];
+        assert!(matches!(verify_fraud_proof(&report[16..], "mychain", sequencer.namespace, &sequencer_key().verifying_key(), &data_root, 4, &next_root), Err(EasyFraudError::WrongBlockHeight { expected: 4, actual: 3 })));
+
+        // the honest block shows up after all. a restart that reads the fraudulent one
+        // again doesn't report it again, and takes the honest one.
+        drop(watchtower);
+        std::fs::remove_file(reports.join("3")).unwrap();
+        block.pairs[1].1 = honest;
+        publish_block(&mut da, sequencer.namespace, "mychain", &sequencer_key(), 3, &block).unwrap();
+        BlockStore::open(&home).unwrap().save_da_height(3).unwrap();
+        let mut watchtower = watch();
+        assert_eq!(watchtower.derivation.da_height, 3);
+        assert_eq!(watchtower.sync().unwrap(), 3);
+        assert!(!reports.join("3").exists());
+        assert_eq!(watchtower.derivation.state.root, sequencer.root);
+        assert_eq!(watchtower.derivation.da_height, 5);
+        drop(watchtower);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
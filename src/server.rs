@@ -0,0 +1,297 @@
+use std::{
+    future::Future,
+    io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Buf, BytesMut};
+use futures::{future::BoxFuture, SinkExt, StreamExt};
+use log::{info, warn};
+use prost::Message;
+use tendermint::v0_38::abci::{
+    response::Exception as ResponseException,
+    Request,
+    Response,
+};
+use tendermint_proto::v0_38::abci::{
+    Request as ProtoRequest,
+    Response as ProtoResponse,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+use tower::{Service, ServiceExt};
+
+use crate::errors::EasyFraudError;
+use crate::state::State;
+
+// how long to hold off accepting after accept fails, it's mostly out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// wraps State so it can be driven by anything that speaks tower.
+// CometBFT opens several connections (consensus, mempool, info, snapshot)
+// and they all share the one State behind the mutex.
+#[derive(Clone)]
+pub struct AbciService {
+    state: Arc<Mutex<State>>,
+}
+
+impl AbciService {
+    pub fn new(state: State) -> Self {
+        AbciService {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+}
+
+impl Service<Request> for AbciService {
+    type Response = Response;
+    type Error = EasyFraudError;
+    type Future = BoxFuture<'static, Result<Response, EasyFraudError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    // State blocks, on the mutex, on sled flushes and on posting blocks to the da
+    // layer, so it runs on a blocking thread instead of holding up the runtime's workers
+    fn call(&mut self, req: Request) -> Self::Future {
+        let state = self.state.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || match req {
+                Request::Commit => commit(&state),
+                req => lock(&state)?.call(req),
+            })
+            .await
+            // the closure panicked, which poisoned the mutex on the way out
+            .unwrap_or(Err(EasyFraudError::StateLockPoisoned))
+        })
+    }
+}
+
+fn lock(state: &Mutex<State>) -> Result<MutexGuard<'_, State>, EasyFraudError> {
+    state.lock().map_err(|_| EasyFraudError::StateLockPoisoned)
+}
+
+// State::commit, with the block posted to the da layer outside the lock. posting is a
+// round trip to the da layer, the other connections shouldn't wait it out. only the
+// consensus connection commits, so nothing else moves state on in the meantime.
+fn commit(state: &Mutex<State>) -> Result<Response, EasyFraudError> {
+    let (blob, mut da) = {
+        let mut state = lock(state)?;
+        match (state.block_blob()?, state.da.take()) {
+            (Some(blob), Some(da)) => (blob, da),
+            (_, da) => {
+                state.da = da;
+                return state.commit();
+            }
+        }
+    };
+    let submitted = da.submit(&[blob]);
+    let mut state = lock(state)?;
+    state.da = Some(da);
+    submitted?;
+    state.commit_published()
+}
+
+// ABCI socket framing: every message is a protobuf prefixed by its
+// length as an unsigned varint.
+pub struct AbciCodec;
+
+impl Decoder for AbciCodec {
+    type Item = ProtoRequest;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ProtoRequest>, io::Error> {
+        let mut buf = &src[..];
+        let len = match prost::encoding::decode_varint(&mut buf) {
+            Ok(len) => len as usize,
+            // a varint is at most 10 bytes, anything shorter may just be incomplete
+            Err(_) if src.len() < 10 => return Ok(None),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        if buf.len() < len {
+            return Ok(None);
+        }
+        let prefix_len = src.len() - buf.len();
+        src.advance(prefix_len);
+        let body = src.split_to(len);
+        ProtoRequest::decode(body)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Encoder<ProtoResponse> for AbciCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: ProtoResponse, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.reserve(item.encoded_len() + 10);
+        item.encode_length_delimited(dst)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+pub struct Server {
+    service: AbciService,
+}
+
+impl Server {
+    pub fn new(service: AbciService) -> Self {
+        Server { service }
+    }
+
+    // address is either tcp://host:port or unix:///path/to/socket
+    pub async fn listen(self, address: &str) -> io::Result<()> {
+        if let Some(addr) = address.strip_prefix("tcp://") {
+            self.serve_tcp(addr).await
+        } else if let Some(path) = address.strip_prefix("unix://") {
+            self.serve_unix(path).await
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported address {}, expected tcp:// or unix://", address),
+            ))
+        }
+    }
+
+    pub async fn serve_tcp(self, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("listening on tcp://{}", listener.local_addr()?);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let stop = shutdown_signal()?;
+        tokio::pin!(stop);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        info!("accepted connection from {}", peer);
+                        connections.spawn(handle_connection(stream, self.service.clone(), shutdown_rx.clone()));
+                    }
+                    // the connections already open are fine, keep serving them
+                    Err(e) => {
+                        warn!("could not accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                },
+                _ = &mut stop => break,
+            }
+        }
+        shutdown(shutdown_tx, connections).await;
+        Ok(())
+    }
+
+    pub async fn serve_unix(self, path: &str) -> io::Result<()> {
+        // a socket left behind by an unclean exit would make bind fail
+        if Path::new(path).exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("listening on unix://{}", path);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let stop = shutdown_signal()?;
+        tokio::pin!(stop);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        info!("accepted connection on unix://{}", path);
+                        connections.spawn(handle_connection(stream, self.service.clone(), shutdown_rx.clone()));
+                    }
+                    Err(e) => {
+                        warn!("could not accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                },
+                _ = &mut stop => break,
+            }
+        }
+        shutdown(shutdown_tx, connections).await;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+// resolves on ctrl-c or SIGTERM, the latter is what process supervisors stop us with.
+// the SIGTERM handler is installed right away, not on first poll.
+pub fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    })
+}
+
+// stop accepting, tell every connection to finish the request it's on, then wait for them
+async fn shutdown(shutdown_tx: watch::Sender<bool>, mut connections: JoinSet<()>) {
+    info!("shutting down, waiting for {} connection(s)", connections.len());
+    let _ = shutdown_tx.send(true);
+    while connections.join_next().await.is_some() {}
+}
+
+async fn handle_connection<S>(stream: S, mut service: AbciService, mut shutdown: watch::Receiver<bool>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed = Framed::new(stream, AbciCodec);
+    loop {
+        let req = tokio::select! {
+            req = framed.next() => req,
+            _ = shutdown.changed() => {
+                // responses fed since the last Flush would be lost otherwise
+                if let Err(e) = framed.flush().await {
+                    warn!("connection error: {}", e);
+                }
+                return;
+            }
+        };
+        let req = match req {
+            Some(Ok(req)) => req,
+            Some(Err(e)) => {
+                warn!("connection error: {}", e);
+                return;
+            }
+            // peer hung up
+            None => return,
+        };
+
+        let (rsp, flush) = match Request::try_from(req) {
+            Ok(req) => {
+                let flush = matches!(req, Request::Flush);
+                let rsp = match service.ready().await {
+                    Ok(svc) => svc.call(req).await,
+                    Err(e) => Err(e),
+                };
+                let rsp = rsp.unwrap_or_else(|e| Response::Exception(ResponseException {
+                    error: e.to_string(),
+                }));
+                (rsp, flush)
+            }
+            Err(e) => (Response::Exception(ResponseException {
+                error: format!("could not decode request: {}", e),
+            }), false),
+        };
+
+        let sent = if flush {
+            framed.send(ProtoResponse::from(rsp)).await
+        } else {
+            framed.feed(ProtoResponse::from(rsp)).await
+        };
+        if let Err(e) = sent {
+            warn!("connection error: {}", e);
+            return;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use monotree::{
+    hasher::Blake3,
+    Hash,
+    Hasher,
+};
+
+use crate::errors::EasyFraudError;
+
+// format 2: the account set as sorted (pubkey, leaf) entries of 64 bytes,
+// split into chunks of CHUNK_ACCOUNTS. metadata is the blake3 hash of every
+// chunk in order, followed by the genesis validators as (address 20, ed25519
+// key 32) records, and the snapshot hash is the hash of the metadata, so each
+// chunk can be checked as soon as it arrives. the validators have to hash to the
+// leaf under state::VALIDATORS_KEY, which is one of the entries.
+// format 1 had no validators, a node restored from it wouldn't know who to pay fees to.
+pub const SNAPSHOT_FORMAT: u32 = 2;
+pub const CHUNK_ACCOUNTS: usize = 1024;
+const ENTRY_SIZE: usize = 64;
+const VALIDATOR_SIZE: usize = 52;
+
+pub struct Snapshot {
+    pub height: u64,
+    pub app_hash: Hash,
+    pub chunks: Vec<Vec<u8>>,
+    pub metadata: Vec<u8>,
+    pub hash: Hash,
+}
+
+impl Snapshot {
+    // entries must be sorted by pubkey so every node cuts the same chunks
+    pub fn new(height: u64, app_hash: Hash, entries: &[([u8; 32], Hash)], validators: &BTreeMap<[u8; 20], [u8; 32]>) -> Self {
+        let hasher = Blake3::new();
+        let chunks = entries.chunks(CHUNK_ACCOUNTS)
+            .map(entries_to_bytes)
+            .collect::<Vec<Vec<u8>>>();
+        let metadata = chunks.iter()
+            .flat_map(|chunk| hasher.digest(chunk))
+            .chain(validators_to_bytes(validators))
+            .collect::<Vec<u8>>();
+        let hash = hasher.digest(&metadata);
+        Snapshot {
+            height,
+            app_hash,
+            chunks,
+            metadata,
+            hash,
+        }
+    }
+}
+
+// a snapshot offered by a peer that we're in the middle of applying
+pub struct Restore {
+    pub height: u64,
+    pub app_hash: Hash,
+    pub chunk_hashes: Vec<Hash>,
+    pub chunks: Vec<Option<Vec<u8>>>,
+    pub validators: BTreeMap<[u8; 20], [u8; 32]>,
+}
+
+impl Restore {
+    pub fn new(height: u64, app_hash: Hash, chunks: u32, hash: &[u8], metadata: &[u8]) -> Result<Self, EasyFraudError> {
+        let hashes_len = chunks as usize * 32;
+        if metadata.len() < hashes_len || Blake3::new().digest(metadata)[..] != *hash {
+            return Err(EasyFraudError::InvalidSnapshot);
+        }
+        let chunk_hashes = metadata[..hashes_len].chunks_exact(32)
+            .map(|h| h.try_into().unwrap())
+            .collect::<Vec<Hash>>();
+        Ok(Restore {
+            height,
+            app_hash,
+            chunks: vec![None; chunk_hashes.len()],
+            chunk_hashes,
+            validators: validators_from_bytes(&metadata[hashes_len..])?,
+        })
+    }
+
+    pub fn apply_chunk(&mut self, index: u32, chunk: &[u8]) -> Result<(), EasyFraudError> {
+        let expected = self.chunk_hashes.get(index as usize)
+            .ok_or(EasyFraudError::InvalidSnapshot)?;
+        if Blake3::new().digest(chunk) != *expected || chunk.len() % ENTRY_SIZE != 0 {
+            return Err(EasyFraudError::InvalidSnapshot);
+        }
+        self.chunks[index as usize] = Some(chunk.to_vec());
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.chunks.iter().all(|chunk| chunk.is_some())
+    }
+
+    pub fn entries(&self) -> Result<Vec<([u8; 32], Hash)>, EasyFraudError> {
+        let data = self.chunks.iter()
+            .map(|chunk| chunk.as_deref().ok_or(EasyFraudError::InvalidSnapshot))
+            .collect::<Result<Vec<&[u8]>, EasyFraudError>>()?
+            .concat();
+        entries_from_bytes(&data)
+    }
+}
+
+pub fn entries_to_bytes(entries: &[([u8; 32], Hash)]) -> Vec<u8> {
+    entries.iter()
+        .flat_map(|(pubkey, leaf)| pubkey.iter().chain(leaf.iter()).copied())
+        .collect()
+}
+
+pub fn entries_from_bytes(data: &[u8]) -> Result<Vec<([u8; 32], Hash)>, EasyFraudError> {
+    if data.len() % ENTRY_SIZE != 0 {
+        return Err(EasyFraudError::InvalidSnapshot);
+    }
+    Ok(data.chunks_exact(ENTRY_SIZE)
+        .map(|entry| (entry[..32].try_into().unwrap(), entry[32..].try_into().unwrap()))
+        .collect())
+}
+
+pub fn validators_to_bytes(validators: &BTreeMap<[u8; 20], [u8; 32]>) -> Vec<u8> {
+    validators.iter()
+        .flat_map(|(address, key)| address.iter().chain(key.iter()).copied())
+        .collect()
+}
+
+pub fn validators_from_bytes(data: &[u8]) -> Result<BTreeMap<[u8; 20], [u8; 32]>, EasyFraudError> {
+    if data.len() % VALIDATOR_SIZE != 0 {
+        return Err(EasyFraudError::InvalidSnapshot);
+    }
+    Ok(data.chunks_exact(VALIDATOR_SIZE)
+        .map(|validator| (validator[..20].try_into().unwrap(), validator[20..].try_into().unwrap()))
+        .collect())
+}
@@ -1,33 +1,83 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+};
+
 use monotree::{
     Monotree,
     Hash,
 };
+use bytes::Bytes;
 use tendermint::{
+    account,
     AppHash,
     block::Height,
+    abci::{
+        types::{
+            ExecTxResult,
+            Snapshot as AbciSnapshot,
+        },
+        Event,
+    },
+    merkle::proof::{
+        ProofOp,
+        ProofOps,
+    },
 };
 use tendermint::v0_38::abci::{
     request::{
+        ApplySnapshotChunk as RequestApplySnapshotChunk,
+        CheckTx as RequestCheckTx,
+        LoadSnapshotChunk as RequestLoadSnapshotChunk,
+        OfferSnapshot as RequestOfferSnapshot,
+        Query as RequestQuery,
+        Info as RequestInfo,
         InitChain as RequestInitChain,
         PrepareProposal as RequestPrepareProposal,
         ProcessProposal as RequestProcessProposal,
         FinalizeBlock as RequestFinalizeBlock,
     },
     response::{
+        ApplySnapshotChunk as ResponseApplySnapshotChunk,
+        ApplySnapshotChunkResult,
+        CheckTx as ResponseCheckTx,
+        Commit as ResponseCommit,
+        Echo as ResponseEcho,
+        ExtendVote as ResponseExtendVote,
+        Info as ResponseInfo,
         InitChain as ResponseInitChain,
+        ListSnapshots as ResponseListSnapshots,
+        LoadSnapshotChunk as ResponseLoadSnapshotChunk,
+        OfferSnapshot as ResponseOfferSnapshot,
         PrepareProposal as ResponsePrepareProposal,
         ProcessProposal as ResponseProcessProposal,
         FinalizeBlock as ResponseFinalizeBlock,
+        Query as ResponseQuery,
+        VerifyVoteExtension as ResponseVerifyVoteExtension,
     },
     Request,
     Response,
 };
-use crate::block::{IncomingBlock, OutgoingBlock, SignedTxnISRPair};
+use celestia_types::{nmt::Namespace, Blob};
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+
+use crate::blob::{block_to_blob, DEFAULT_NAMESPACE};
+use crate::da::DataAvailability;
+use crate::block::{Header, IncomingBlock, OutgoingBlock, SignedTxnISRPair};
 use crate::errors::EasyFraudError;
+use crate::store::BlockStore;
+use crate::db::{TreeDB, MEMORY_DB};
+use crate::snapshot::{validators_to_bytes, Restore, Snapshot, SNAPSHOT_FORMAT};
 use crate::transaction::{
     SignedTransaction,
     Transaction,
+    Tx,
 };
+use crate::utils::{account_leaf, from_hex, leaf_to_nonce, leaf_to_num, proof_to_bytes, to_hex};
+
+// every transfer costs the same, there's nothing to meter yet
+const TRANSFER_GAS: i64 = 1;
 
 pub struct AccountBalancePair {
     pub pubkey: [u8; 32],
@@ -60,113 +110,337 @@ impl AccountBalancePair {
 pub struct State {
     pub initialized: bool,
     pub chain_id: String,
-    pub tree: Monotree,
+    pub tree: Monotree<TreeDB>,
     pub root: Option<Hash>,
+    // height of the last committed block, 0 until the first commit
     pub height: u64,
     // keep track of the pre-image of everything we changed,
     // so we can revert back if needed.
     pub current_block: Option<OutgoingBlock>,
+    // root as of the last commit, volatile_diffs are relative to it
     pub volatile_root: Option<Hash>,
     pub volatile_diffs: Vec<AccountBalanceLeafPair>,
+    // committed app hash at every retained height
+    pub app_hashes: BTreeMap<u64, Hash>,
+    // how many blocks CometBFT should keep, 0 keeps everything
+    pub retain_blocks: u64,
+    // amount each sender has already committed to in the mempool,
+    // on top of its committed balance. rebuilt by rechecks after every commit.
+    pub pending_spend: HashMap<[u8; 32], u64>,
+    // next nonce of each sender with transactions in the mempool,
+    // the committed nonce doesn't count them yet. cleared the same way.
+    pub pending_nonces: HashMap<[u8; 32], u64>,
+    // bookkeeping persisted next to the tree, None keeps everything in memory
+    pub store: Option<BlockStore>,
+    // every pubkey that was ever written to the tree. monotree can't
+    // enumerate its leaves, and snapshots need the whole account set.
+    pub accounts: BTreeSet<[u8; 32]>,
+    // accounts created since the last commit, dropped again on revert
+    pub pending_accounts: Vec<[u8; 32]>,
+    // take a snapshot every snapshot_interval heights, 0 disables snapshots
+    pub snapshot_interval: u64,
+    pub snapshot_keep_recent: usize,
+    pub snapshots: BTreeMap<u64, Snapshot>,
+    pub restore: Option<Restore>,
+    // ed25519 consensus key of every genesis validator, by its address. a proposer's
+    // fees go to the account of its key, it can spend them with the key it signs blocks with.
+    pub validators: BTreeMap<[u8; 20], [u8; 32]>,
+    // account the fees of the block being run go to, None burns them
+    pub fee_recipient: Option<[u8; 32]>,
+    // celestia namespace the rollup's blocks are posted to
+    pub namespace: Namespace,
+    // committed blocks are published here, None doesn't publish them
+    pub da: Option<Box<dyn DataAvailability + Send>>,
+    // what published blocks are signed with, followers only take blocks signed by its key
+    pub sequencer_key: Option<SigningKey>,
 }
 
 impl State {
-    pub fn call(&mut self, req: Request) {
-        
-        let rsp = match req {
+    pub fn new(chain_id: impl Into<String>) -> Self {
+        State {
+            initialized: false,
+            chain_id: chain_id.into(),
+            tree: Monotree::new(MEMORY_DB),
+            root: None,
+            current_block: None,
+            height: 0,
+            volatile_root: None,
+            volatile_diffs: vec![],
+            app_hashes: BTreeMap::new(),
+            retain_blocks: 0,
+            pending_spend: HashMap::new(),
+            pending_nonces: HashMap::new(),
+            store: None,
+            accounts: BTreeSet::new(),
+            pending_accounts: vec![],
+            snapshot_interval: 0,
+            snapshot_keep_recent: 2,
+            snapshots: BTreeMap::new(),
+            restore: None,
+            validators: BTreeMap::new(),
+            fee_recipient: None,
+            namespace: Namespace::new(0, DEFAULT_NAMESPACE).unwrap(),
+            da: None,
+            sequencer_key: None,
+        }
+    }
+
+    // open the tree and bookkeeping persisted under home and pick up at the last commit.
+    // with nothing committed yet CometBFT will send InitChain as usual.
+    pub fn open(chain_id: impl Into<String>, home: impl AsRef<Path>) -> Result<Self, EasyFraudError> {
+        let home = home.as_ref();
+        let store = BlockStore::open(home)?;
+        let tree_path = home.join("tree").to_string_lossy().into_owned();
+        TreeDB::check(&tree_path).map_err(EasyFraudError::Storage)?;
+
+        let mut state = State::new(chain_id);
+        state.tree = Monotree::new(&tree_path);
+        // the tree is flushed before meta is written, so the root in meta is always in the tree
+        if let Some((height, app_hash)) = store.load_meta()? {
+            state.root = Some(app_hash);
+            state.volatile_root = Some(app_hash);
+            state.height = height;
+            state.initialized = true;
+            state.accounts = store.load_accounts()?.into_iter().collect();
+            state.app_hashes = store.load_app_hashes(height)?;
+            state.validators = store.load_validators()?;
+        }
+        state.store = Some(store);
+        Ok(state)
+    }
+
+    pub fn call(&mut self, req: Request) -> Result<Response, EasyFraudError> {
+        match req {
             // handled messages
             Request::InitChain(init_chain) => self.init_chain(init_chain),
-            /*Request::Info(_) => Response::Info(self.info()),
-            Request::Query(query) => Response::Query(self.query(query.data)),
-            Request::DeliverTx(deliver_tx) => Response::DeliverTx(self.deliver_tx(deliver_tx.tx)),
-            Request::Commit => Response::Commit(self.commit()),
-            // unhandled messages
-            Request::Flush => Response::Flush,
-            Request::Echo(_) => Response::Echo(Default::default()),
-            Request::BeginBlock(_) => Response::BeginBlock(Default::default()),
-            Request::CheckTx(_) => Response::CheckTx(Default::default()),
-            Request::EndBlock(_) => Response::EndBlock(Default::default()),
-            Request::ListSnapshots => Response::ListSnapshots(Default::default()),
-            Request::OfferSnapshot(_) => Response::OfferSnapshot(Default::default()),
-            Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
-            Request::ApplySnapshotChunk(_) => Response::ApplySnapshotChunk(Default::default()),
-
-            // Note: https://github.com/tendermint/tendermint/blob/v0.37.x/spec/abci/abci%2B%2B_tmint_expected_behavior.md#adapting-existing-applications-that-use-abci
-            Request::PrepareProposal(prepare_prop) => Response::PrepareProposal(PrepareProposal {
-                txs: prepare_prop.txs,
-            }),
-            Request::ProcessProposal(..) => {
-                Response::ProcessProposal(response::ProcessProposal::Accept)
-            }*/
-            _ => {
-                panic!("unimplemented");
+            Request::Info(info) => self.info(info),
+            Request::PrepareProposal(prepare_prop) => self.prepare_proposal(prepare_prop),
+            Request::ProcessProposal(process_prop) => {
+                // a proposal we can't make sense of gets rejected, it shouldn't take the node down
+                match self.process_proposal(process_prop) {
+                    Ok(()) => Ok(Response::ProcessProposal(ResponseProcessProposal::Accept)),
+                    Err(_) => Ok(Response::ProcessProposal(ResponseProcessProposal::Reject)),
+                }
             }
+            Request::FinalizeBlock(finalize_block) => self.finalize_block(finalize_block),
+            Request::Commit => self.commit(),
+            Request::CheckTx(check_tx) => self.check_tx(check_tx),
+            Request::Query(query) => self.query(query),
+            Request::ListSnapshots => self.list_snapshots(),
+            Request::OfferSnapshot(offer) => self.offer_snapshot(offer),
+            Request::LoadSnapshotChunk(load) => self.load_snapshot_chunk(load),
+            Request::ApplySnapshotChunk(apply) => self.apply_snapshot_chunk(apply),
+
+            // unhandled messages
+            Request::Flush => Ok(Response::Flush),
+            Request::Echo(echo) => Ok(Response::Echo(ResponseEcho {
+                message: echo.message,
+            })),
+            Request::ExtendVote(_) => Ok(Response::ExtendVote(ResponseExtendVote {
+                vote_extension: Bytes::new(),
+            })),
+            Request::VerifyVoteExtension(_) => Ok(Response::VerifyVoteExtension(ResponseVerifyVoteExtension::Accept)),
+        }
+    }
+
+    // CometBFT compares this against its own block store on startup
+    // and replays whatever we're missing through FinalizeBlock/Commit
+    pub fn info(&self, _req: RequestInfo) -> Result<Response, EasyFraudError> {
+        let last_commit = match &self.store {
+            Some(store) => store.load_meta()?,
+            None => self.app_hashes.get(&self.height)
+                .filter(|_| self.height > 0)
+                .map(|root| (self.height, *root)),
+        };
+        // before the first commit CometBFT expects height 0 and an empty app hash
+        let (last_block_height, last_block_app_hash) = match last_commit {
+            Some((height, root)) => (height, AppHash::try_from(root.to_vec())
+                .map_err(|_| EasyFraudError::NullApphash)?),
+            None => (0, AppHash::default()),
         };
+        Ok(Response::Info(ResponseInfo {
+            data: "easyfraud".into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            app_version: 1,
+            last_block_height: Height::try_from(last_block_height)
+                .map_err(|_| EasyFraudError::InvalidHeight)?,
+            last_block_app_hash,
+        }))
+    }
 
+    pub fn app_hash(&self) -> Result<AppHash, EasyFraudError> {
+        let root = self.root.ok_or(EasyFraudError::NullApphash)?;
+        AppHash::try_from(root.to_vec())
+            .map_err(|_| EasyFraudError::NullApphash)
     }
 
-    // verify the transaction against the current state, then execute it
-    // save the old diffs
-    pub fn verify_and_run_transaction(&mut self, stx: &SignedTransaction) -> Result<Option<Hash>, EasyFraudError> {
-        let txn = match stx.verify_and_deserialize() {
-            Ok(txn) => txn,
-            Err(_) => {
-                return Ok(None);
-            }
+    // supported paths:
+    //   /balance/<hex pubkey>   balance as a u64 le, with prove=true also a
+    //                           monotree inclusion proof against the app hash at height
+    //   /nonce/<hex pubkey>     the nonce the account's next transfer has to be signed
+    //                           with as a u64 le, proven the same way
+    pub fn query(&mut self, req: RequestQuery) -> Result<Response, EasyFraudError> {
+        let rsp = self.query_account(&req).unwrap_or_else(|e| ResponseQuery {
+            code: e.code().into(),
+            log: e.to_string(),
+            height: req.height,
+            ..Default::default()
+        });
+        Ok(Response::Query(rsp))
+    }
+
+    fn query_account(&mut self, req: &RequestQuery) -> Result<ResponseQuery, EasyFraudError> {
+        let (field, pubkey): (fn(&[u8; 32]) -> u64, &str) = if let Some(pubkey) = req.path.strip_prefix("/balance/") {
+            (leaf_to_num, pubkey)
+        } else if let Some(pubkey) = req.path.strip_prefix("/nonce/") {
+            (leaf_to_nonce, pubkey)
+        } else {
+            return Err(EasyFraudError::UnhandledRequest);
         };
+        let pubkey: [u8; 32] = from_hex(pubkey)?
+            .try_into()
+            .map_err(|_| EasyFraudError::InvalidHex)?;
+        // height 0 means latest committed
+        let height = match req.height.value() {
+            0 => self.height,
+            height => height,
+        };
+        let root = *self.app_hashes.get(&height)
+            .ok_or(EasyFraudError::UnknownHeight(height))?;
 
-        // transaction must have > 0 satoshi
-        if txn.amount <= 0 {
-            return Ok(None);
+        let leaf = self.tree.get(Some(&root), &pubkey)
+            .map_err(|_| EasyFraudError::TreeGetError)?;
+        // monotree can only prove inclusion, so an absent account is an error code of its
+        // own instead of a zero balance a client could take for a proven one
+        let leaf = leaf.ok_or(EasyFraudError::AccountNotFound)?;
+
+        let proof = if req.prove {
+            let proof = self.tree.get_merkle_proof(Some(&root), &pubkey)
+                .map_err(|_| EasyFraudError::TreeGetError)?
+                .ok_or(EasyFraudError::InvalidProof)?;
+            // the leaf goes along too, the proof is over the whole 32 byte leaf not just the field
+            Some(ProofOps {
+                ops: vec![ProofOp {
+                    field_type: "monotree:blake3".into(),
+                    key: pubkey.to_vec(),
+                    data: [&leaf[..], &proof_to_bytes(&proof)[..]].concat(),
+                }],
+            })
+        } else {
+            None
+        };
+
+        Ok(ResponseQuery {
+            log: "exists".into(),
+            key: pubkey.to_vec().into(),
+            value: field(&leaf).to_le_bytes().to_vec().into(),
+            proof,
+            height: Height::try_from(height).map_err(|_| EasyFraudError::InvalidHeight)?,
+            ..Default::default()
+        })
+    }
+
+    // mempool admission. New and Recheck are treated the same, the overlay
+    // is cleared on commit so rechecks are measured against the new balances.
+    pub fn check_tx(&mut self, req: RequestCheckTx) -> Result<Response, EasyFraudError> {
+        let rsp = match self.admit_transaction(&req.tx) {
+            Ok(()) => ResponseCheckTx {
+                gas_wanted: TRANSFER_GAS,
+                ..Default::default()
+            },
+            Err(e) => ResponseCheckTx {
+                code: e.code().into(),
+                log: e.to_string(),
+                ..Default::default()
+            },
+        };
+        Ok(Response::CheckTx(rsp))
+    }
+
+    fn admit_transaction(&mut self, tx: &[u8]) -> Result<(), EasyFraudError> {
+        let txn = match Tx::decode(tx)? {
+            Tx::Transfer(stx) => stx.verify_and_deserialize(&self.chain_id)?,
+        };
+        if txn.amount == 0 {
+            return Err(EasyFraudError::ZeroAmount);
+        }
+        if txn.sender_pubkey == txn.recipient_pubkey {
+            return Err(EasyFraudError::SelfTransfer);
+        }
+        if txn.recipient_pubkey == VALIDATORS_KEY {
+            return Err(EasyFraudError::ReservedAccount);
         }
 
-        let old_sender_balance_leaf: [u8; 32] = self.tree.get(self.root.as_ref(), &txn.sender_pubkey)
+        // only committed state counts, whatever the current block is doing may still be reverted
+        let balance_leaf = self.tree.get(self.volatile_root.as_ref(), &txn.sender_pubkey)
             .map_err(|_| EasyFraudError::TreeGetError)?
             .ok_or(EasyFraudError::SenderNotInitialized)?;
-        let mut old_sender_balance_buf = [0; 8];
-        old_sender_balance_buf.copy_from_slice(&old_sender_balance_leaf[24..]);
-        let old_sender_balance = u64::from_le_bytes(old_sender_balance_buf);
-
-        let old_recipient_balance_leaf: Option<[u8; 32]> = self.tree.get(self.root.as_ref(), &txn.recipient_pubkey)
-            .map_err(|_| EasyFraudError::TreeGetError)?;
-        let mut old_recipient_balance_buf = [0; 8];
-        old_recipient_balance_buf.copy_from_slice(&old_recipient_balance_leaf.unwrap_or([0; 32])[24..]);
-        let old_recipient_balance = u64::from_le_bytes(old_recipient_balance_buf);
-        
-        // validate the transaction
-        if old_sender_balance <= txn.amount {
-            return Ok(None)
+        // one transaction per nonce, in order. a replay, or a second transaction
+        // racing the first for its nonce, is turned away here already.
+        let expected = self.pending_nonces.get(&txn.sender_pubkey).copied()
+            .unwrap_or_else(|| leaf_to_nonce(&balance_leaf));
+        if txn.nonce != expected {
+            return Err(EasyFraudError::InvalidNonce { expected, actual: txn.nonce });
+        }
+        let pending = self.pending_spend.get(&txn.sender_pubkey).copied().unwrap_or(0);
+        let total = pending.checked_add(txn.amount)
+            .and_then(|total| total.checked_add(txn.fee))
+            .ok_or(EasyFraudError::InsufficientBalance)?;
+        // same rule as verify_and_run_transaction, the sender can't be drained to zero
+        if leaf_to_num(&balance_leaf) <= total {
+            return Err(EasyFraudError::InsufficientBalance);
         }
+        self.pending_spend.insert(txn.sender_pubkey, total);
+        self.pending_nonces.insert(txn.sender_pubkey, expected + 1);
+        Ok(())
+    }
 
-        let mut new_sender_balance_leaf = [0; 32];
-        new_sender_balance_leaf[24..].copy_from_slice(&(old_sender_balance - txn.amount).to_le_bytes()[..]);
+    // run a transaction of any kind on the current state
+    pub fn execute_transaction(&mut self, tx: &Tx) -> Result<Option<Hash>, EasyFraudError> {
+        match tx {
+            Tx::Transfer(stx) => self.verify_and_run_transaction(stx),
+        }
+    }
 
-        let mut new_recipient_balance_leaf = [0; 32];
-        new_recipient_balance_leaf[24..].copy_from_slice(&(old_recipient_balance + txn.amount).to_le_bytes()[..]);
+    // execute_transaction for one whose signature was already checked
+    pub fn run_verified_transaction(&mut self, tx: &Tx) -> Result<Option<Hash>, EasyFraudError> {
+        match tx {
+            Tx::Transfer(stx) => self.run_transfer(Transaction::deserialize(stx.transaction_data)?),
+        }
+    }
 
-        let first_root = self.tree.insert(self.root.as_ref(), &txn.sender_pubkey, &new_sender_balance_leaf)
-            .map_err(|_| EasyFraudError::TreeInsertionError)?;
+    // verify the transaction against the current state, then execute it
+    pub fn verify_and_run_transaction(&mut self, stx: &SignedTransaction) -> Result<Option<Hash>, EasyFraudError> {
+        let txn = stx.verify_and_deserialize(&self.chain_id)?;
+        self.run_transfer(txn)
+    }
 
-        let second_root = self.tree.insert(first_root.as_ref(), &txn.recipient_pubkey, &new_recipient_balance_leaf);
-        if let Ok(updated_root) = second_root {
-            self.root = updated_root;
-            // Transaction execution was success. Now save the old diffs.
-            self.volatile_diffs.push(AccountBalanceLeafPair {
-                pubkey: txn.sender_pubkey,
-                balance: Some(old_sender_balance_leaf),
-            });
+    // save the old diffs
+    fn run_transfer(&mut self, txn: Transaction) -> Result<Option<Hash>, EasyFraudError> {
+        let transfer = apply_verified_transfer(&mut self.tree, self.root.as_ref(), self.fee_recipient.as_ref(), txn)?;
+        self.root = transfer.root;
+        if self.accounts.insert(transfer.txn.recipient_pubkey) {
+            self.pending_accounts.push(transfer.txn.recipient_pubkey);
+        }
+        // Transaction execution was success. Now save the old diffs.
+        self.volatile_diffs.push(AccountBalanceLeafPair {
+            pubkey: transfer.txn.sender_pubkey,
+            balance: Some(transfer.old_sender_leaf),
+        });
+        self.volatile_diffs.push(AccountBalanceLeafPair {
+            pubkey: transfer.txn.recipient_pubkey,
+            balance: transfer.old_recipient_leaf,
+        });
+        if let Some((fee_recipient, old_leaf)) = transfer.fee_recipient {
+            if self.accounts.insert(fee_recipient) {
+                self.pending_accounts.push(fee_recipient);
+            }
             self.volatile_diffs.push(AccountBalanceLeafPair {
-                pubkey: txn.recipient_pubkey,
-                balance: old_recipient_balance_leaf,
+                pubkey: fee_recipient,
+                balance: old_leaf,
             });
-            return Ok(updated_root);
         }
-
-        // second insertion failed. must revert first.
-        let reverted_root = self.tree.insert(first_root.as_ref(), &txn.sender_pubkey, &old_sender_balance_leaf)
-            .map_err(|_| EasyFraudError::CouldNotRevert)?;
-        self.root = reverted_root;
-        Ok(None)
+        Ok(transfer.root)
     }
 
     pub fn init_chain(&mut self, req: RequestInitChain) -> Result<Response, EasyFraudError> {
@@ -177,47 +451,84 @@ impl State {
             return Err(EasyFraudError::InvalidGenesisHeight)
         }
 
-        req.app_state_bytes
+        // validators with other kinds of keys have no account to be paid into
+        self.validators = req.validators.iter()
+            .filter_map(|validator| validator.pub_key.ed25519())
+            .filter_map(|key| key.as_bytes().try_into().ok())
+            .map(|key: [u8; 32]| (validator_address(&key), key))
+            .collect();
+        self.apply_genesis(&req.app_state_bytes)?;
+        let root = self.root.ok_or(EasyFraudError::NullApphash)?;
+        // meta stays empty until the first commit, so a restart before then gets InitChain again
+        if let Some(store) = &self.store {
+            store.save_validators(&self.validators)?;
+            store.append_accounts(&self.pending_accounts)?;
+            store.append_app_hash(0, &root)?;
+        }
+        self.pending_accounts = vec![];
+
+        let app_hash = root.to_vec();
+        Ok(Response::InitChain(ResponseInitChain{
+            consensus_params: None,
+            validators: vec![],
+            app_hash: AppHash::try_from(app_hash)
+                .map_err(|_| EasyFraudError::InvalidGenesisAppHash)?,
+        }))
+    }
+
+    fn apply_genesis(&mut self, app_state_bytes: &[u8]) -> Result<(), EasyFraudError> {
+        app_state_bytes
             .chunks_exact(40)
             .try_for_each(|chunk| {
                 let pair = AccountBalancePair::deserialize(chunk.try_into()
                     .map_err(|_| EasyFraudError::GenesisAccountDeserialization)?)?;
-                let mut balance_buf = [0; 32];
-                balance_buf[24..].copy_from_slice(&pair.balance.to_le_bytes());
-                let new_root = self.tree.insert(self.root.as_ref(), &pair.pubkey, &balance_buf)
+                let new_root = self.tree.insert(self.root.as_ref(), &pair.pubkey, &account_leaf(0, pair.balance))
                     .map_err(|_| EasyFraudError::TreeInsertionError)?;
                 self.root = new_root;
+                if self.accounts.insert(pair.pubkey) {
+                    self.pending_accounts.push(pair.pubkey);
+                }
                 Ok(())
             })?;
+        // the validators go in last, over any genesis account under their key
+        self.root = self.tree.insert(self.root.as_ref(), &VALIDATORS_KEY, &validators_leaf(&self.validators))
+            .map_err(|_| EasyFraudError::TreeInsertionError)?;
+        if self.accounts.insert(VALIDATORS_KEY) {
+            self.pending_accounts.push(VALIDATORS_KEY);
+        }
 
-        self.height = 1;
+        // nothing is committed yet, the first block will be initial_height
+        self.height = 0;
+        self.initialized = true;
 
         self.volatile_diffs = vec![];
-
-        let app_hash = self.root.ok_or(EasyFraudError::NullApphash)?.to_vec();
         self.volatile_root = self.root;
-
-        Ok(Response::InitChain(ResponseInitChain{
-            consensus_params: None,
-            validators: vec![],
-            app_hash: AppHash::try_from(app_hash)
-                .map_err(|_| EasyFraudError::InvalidGenesisAppHash)?,
-        }))
+        self.app_hashes.insert(0, self.root.ok_or(EasyFraudError::NullApphash)?);
+        Ok(())
     }
 
     pub fn prepare_proposal(&mut self, req: RequestPrepareProposal) -> Result<Response, EasyFraudError> {
+        // every transaction goes out with its 32 byte ISR, the ones past max_tx_bytes
+        // are left in the mempool for the next block
+        let mut size = 0;
         let incoming_block = IncomingBlock {
+            // whatever doesn't decode is dropped by process like any other invalid transaction
             signed_transactions: req.txs.iter()
-                .map(|tx| {
-                    let mut buf = [0; 136];
-                    buf.copy_from_slice(tx);
-                    buf
+                .take_while(|tx| {
+                    size += tx.len() as i64 + 32;
+                    size <= req.max_tx_bytes
                 })
+                .map(|tx| tx.to_vec())
                 .collect(),
         };
+        // speculative nodes stay in the db batch and are dropped by the next prepare
+        self.tree.prepare();
+        self.fee_recipient = self.proposer_account(&req.proposer_address);
         self.current_block = Some(incoming_block.process(self)?);
+        // the proposal isn't decided yet, FinalizeBlock is what applies it
+        self.revert_volatile();
         Ok(Response::PrepareProposal(ResponsePrepareProposal{
-            // unwrap is safe because we set it on line 218
+            // unwrap is safe, it was set just above
             txs: self.current_block.as_ref().unwrap().pairs.iter()
                 .map(|pair| {
                     pair.serialize()
@@ -228,24 +539,417 @@ impl State {
         }))
     }
 
+    // re-execute the proposal and check every ISR the proposer claimed.
+    // execution is only speculative, FinalizeBlock is what applies it.
     pub fn process_proposal(&mut self, req: RequestProcessProposal) -> Result<(), EasyFraudError> {
-        for pair in req.txs {
-            let pair = SignedTxnISRPair::from_slice(&pair)
+        self.tree.prepare();
+        self.fee_recipient = self.proposer_account(&req.proposer_address);
+        let result = req.txs.iter().try_for_each(|tx| {
+            let pair = SignedTxnISRPair::from_slice(tx)
                 .map_err(|_| EasyFraudError::DeserializePairsError)?;
+            let isr = self.execute_transaction(&Tx::decode(&pair.0)?)?
+                .ok_or(EasyFraudError::NoRoot)?;
+            if isr != pair.1 {
+                return Err(EasyFraudError::IsrMismatch);
+            }
+            Ok(())
+        });
+        self.revert_volatile();
+        result
+    }
+
+    // apply the decided block. every pair gets an ExecTxResult, failed ones carry the error code
+    pub fn finalize_block(&mut self, req: RequestFinalizeBlock) -> Result<Response, EasyFraudError> {
+        if req.height.value() != self.height + 1 {
+            return Err(EasyFraudError::InvalidHeight);
+        }
+        let txs = req.txs.iter().map(|tx| tx.to_vec()).collect::<Vec<Vec<u8>>>();
+        // everything the block writes is held back until commit flushes it in one batch
+        self.tree.prepare();
+        self.fee_recipient = self.proposer_account(&req.proposer_address);
+        let tx_results = self.execute_block(&txs);
+
+        Ok(Response::FinalizeBlock(ResponseFinalizeBlock {
+            events: vec![],
+            tx_results,
+            validator_updates: vec![],
+            consensus_param_updates: None,
+            app_hash: self.app_hash()?,
+        }))
+    }
+
+    // run a decided block on top of the current root
+    fn execute_block(&mut self, txs: &[Vec<u8>]) -> Vec<ExecTxResult> {
+        let mut outgoing_block = OutgoingBlock {
+            header: Header {
+                apphash: self.root,
+                fee_recipient: self.fee_recipient,
+            },
+            pairs: vec![],
+        };
+        let mut tx_results = Vec::with_capacity(txs.len());
+        for tx in txs.iter() {
+            let result = SignedTxnISRPair::from_slice(tx)
+                .and_then(|pair| {
+                    let tx = Tx::decode(&pair.0)?;
+                    let isr = self.execute_transaction(&tx)?
+                        .ok_or(EasyFraudError::NoRoot)?;
+                    let event = match &tx {
+                        Tx::Transfer(stx) => {
+                            let txn = Transaction::deserialize(stx.transaction_data)?;
+                            Event::new("transfer", [
+                                ("sender", to_hex(&txn.sender_pubkey), true),
+                                ("recipient", to_hex(&txn.recipient_pubkey), true),
+                                ("amount", txn.amount.to_string(), false),
+                                ("fee", txn.fee.to_string(), false),
+                            ])
+                        }
+                    };
+                    Ok((pair.0, isr, event))
+                });
+            tx_results.push(match result {
+                Ok((envelope, isr, event)) => {
+                    outgoing_block.pairs.push(SignedTxnISRPair(envelope, isr));
+                    ExecTxResult {
+                        data: Bytes::copy_from_slice(&isr),
+                        gas_wanted: TRANSFER_GAS,
+                        gas_used: TRANSFER_GAS,
+                        events: vec![event],
+                        ..Default::default()
+                    }
+                }
+                Err(e) => ExecTxResult {
+                    code: e.code().into(),
+                    log: e.to_string(),
+                    gas_wanted: TRANSFER_GAS,
+                    ..Default::default()
+                },
+            });
+        }
+        outgoing_block.header.apphash = self.root;
+        self.current_block = Some(outgoing_block);
+        tx_results
+    }
+
+    // the block FinalizeBlock left behind as the blob commit posts, None without a da layer
+    pub fn block_blob(&self) -> Result<Option<Blob>, EasyFraudError> {
+        let (Some(_), Some(block)) = (self.da.as_ref(), self.current_block.as_ref()) else {
+            return Ok(None);
+        };
+        let key = self.sequencer_key.as_ref()
+            .ok_or(EasyFraudError::InvalidArgument("--sequencer-key".into()))?;
+        block_to_blob(self.namespace, &self.chain_id, key, self.height + 1, block).map(Some)
+    }
+
+    // post the block FinalizeBlock left behind, then promote its root to committed state.
+    // if posting fails nothing has changed, CometBFT replays the block after a restart
+    // and it gets posted then.
+    pub fn commit(&mut self) -> Result<Response, EasyFraudError> {
+        if let (Some(blob), Some(da)) = (self.block_blob()?, self.da.as_mut()) {
+            da.submit(&[blob])?;
+        }
+        self.commit_published()
+    }
+
+    // commit, for a block block_blob already went out to the da layer for.
+    // after this revert_volatile can no longer undo the block.
+    pub fn commit_published(&mut self) -> Result<Response, EasyFraudError> {
+        let root = self.promote_volatile()?;
+        self.tree.commit();
+        if let Some(store) = &self.store {
+            store.append_accounts(&self.pending_accounts)?;
+            store.append_app_hash(self.height, &root)?;
+            store.save_meta(self.height, &root)?;
+        }
+        self.pending_accounts = vec![];
+        if self.snapshot_interval > 0 && self.height % self.snapshot_interval == 0 {
+            self.take_snapshot(root)?;
+        }
+
+        let retain_height = match self.retain_blocks {
+            0 => 0,
+            n => self.height.saturating_sub(n) + 1,
+        };
+        // we can't serve proofs against heights CometBFT is about to prune anyway
+        self.app_hashes = self.app_hashes.split_off(&retain_height);
+
+        Ok(Response::Commit(ResponseCommit {
+            data: Bytes::new(),
+            retain_height: Height::try_from(retain_height)
+                .map_err(|_| EasyFraudError::InvalidHeight)?,
+        }))
+    }
+
+    fn promote_volatile(&mut self) -> Result<Hash, EasyFraudError> {
+        let root = self.root.ok_or(EasyFraudError::NullApphash)?;
+        self.height += 1;
+        self.volatile_root = self.root;
+        self.volatile_diffs = vec![];
+        self.app_hashes.insert(self.height, root);
+        self.pending_spend.clear();
+        self.pending_nonces.clear();
+        Ok(root)
+    }
+
+    fn take_snapshot(&mut self, root: Hash) -> Result<(), EasyFraudError> {
+        let mut entries = Vec::with_capacity(self.accounts.len());
+        for pubkey in self.accounts.iter() {
+            if let Some(leaf) = self.tree.get(Some(&root), pubkey)
+                .map_err(|_| EasyFraudError::TreeGetError)? {
+                entries.push((*pubkey, leaf));
+            }
+        }
+        self.snapshots.insert(self.height, Snapshot::new(self.height, root, &entries, &self.validators));
+        while self.snapshots.len() > self.snapshot_keep_recent {
+            self.snapshots.pop_first();
         }
         Ok(())
     }
 
-    pub fn revert_volatile(&mut self) {
-        self.volatile_diffs.iter().for_each(|pair| {
-            if let Some(balance_leaf) = pair.balance {
-                let _ = self.tree.insert(self.volatile_root.as_ref(), &pair.pubkey, &balance_leaf);
-            } else {
-                let _ = self.tree.remove(self.volatile_root.as_ref(), &pair.pubkey);
+    pub fn list_snapshots(&self) -> Result<Response, EasyFraudError> {
+        let snapshots = self.snapshots.values()
+            .map(|snapshot| Ok(AbciSnapshot {
+                height: Height::try_from(snapshot.height)
+                    .map_err(|_| EasyFraudError::InvalidHeight)?,
+                format: SNAPSHOT_FORMAT,
+                chunks: snapshot.chunks.len() as u32,
+                hash: Bytes::copy_from_slice(&snapshot.hash),
+                metadata: snapshot.metadata.clone().into(),
+            }))
+            .collect::<Result<Vec<AbciSnapshot>, EasyFraudError>>()?;
+        Ok(Response::ListSnapshots(ResponseListSnapshots { snapshots }))
+    }
+
+    pub fn load_snapshot_chunk(&self, req: RequestLoadSnapshotChunk) -> Result<Response, EasyFraudError> {
+        let chunk = self.snapshots.get(&req.height.value())
+            .filter(|_| req.format == SNAPSHOT_FORMAT)
+            .and_then(|snapshot| snapshot.chunks.get(req.chunk as usize))
+            .cloned()
+            .unwrap_or_default();
+        Ok(Response::LoadSnapshotChunk(ResponseLoadSnapshotChunk {
+            chunk: chunk.into(),
+        }))
+    }
+
+    // the app hash comes from CometBFT's light client, so it's the one thing here we can trust
+    pub fn offer_snapshot(&mut self, req: RequestOfferSnapshot) -> Result<Response, EasyFraudError> {
+        if req.snapshot.format != SNAPSHOT_FORMAT {
+            return Ok(Response::OfferSnapshot(ResponseOfferSnapshot::RejectFormat));
+        }
+        let app_hash: Hash = match req.app_hash.as_bytes().try_into() {
+            Ok(app_hash) => app_hash,
+            Err(_) => return Ok(Response::OfferSnapshot(ResponseOfferSnapshot::Reject)),
+        };
+        let restore = Restore::new(
+            req.snapshot.height.value(),
+            app_hash,
+            req.snapshot.chunks,
+            &req.snapshot.hash,
+            &req.snapshot.metadata,
+        );
+        match restore {
+            Ok(restore) => {
+                self.restore = Some(restore);
+                Ok(Response::OfferSnapshot(ResponseOfferSnapshot::Accept))
             }
-        });
+            Err(_) => Ok(Response::OfferSnapshot(ResponseOfferSnapshot::Reject)),
+        }
+    }
+
+    pub fn apply_snapshot_chunk(&mut self, req: RequestApplySnapshotChunk) -> Result<Response, EasyFraudError> {
+        let rsp = |result, refetch_chunks, reject_senders| Ok(Response::ApplySnapshotChunk(ResponseApplySnapshotChunk {
+            result,
+            refetch_chunks,
+            reject_senders,
+        }));
+        let restore = match self.restore.as_mut() {
+            Some(restore) => restore,
+            None => return rsp(ApplySnapshotChunkResult::Abort, vec![], vec![]),
+        };
+        // a chunk that doesn't match the metadata came from a bad peer, ask someone else
+        if restore.apply_chunk(req.index, &req.chunk).is_err() {
+            return rsp(ApplySnapshotChunkResult::Retry, vec![req.index], vec![req.sender]);
+        }
+        if !restore.is_complete() {
+            return rsp(ApplySnapshotChunkResult::Accept, vec![], vec![]);
+        }
+
+        // unwrap is safe, we just borrowed it
+        let restore = self.restore.take().unwrap();
+        let entries = restore.entries()?;
+        // the validators come from the metadata, they have to be the ones the app hash commits to
+        let committed = entries.iter().find(|(pubkey, _)| *pubkey == VALIDATORS_KEY);
+        if committed.map(|(_, leaf)| *leaf) != Some(validators_leaf(&restore.validators)) {
+            return rsp(ApplySnapshotChunkResult::RejectSnapshot, vec![], vec![]);
+        }
+        // the whole tree goes in one batch, a rejected snapshot leaves it to be dropped by
+        // the next prepare and an accepted one is flushed to disk before meta points at it
+        self.tree.prepare();
+        if self.apply_snapshot_entries(restore.height, restore.app_hash, &entries).is_err() {
+            return rsp(ApplySnapshotChunkResult::RejectSnapshot, vec![], vec![]);
+        }
+        self.tree.commit();
+        // InitChain never comes for a node that starts from a snapshot
+        self.validators = restore.validators;
+        if let Some(store) = &self.store {
+            store.save_validators(&self.validators)?;
+            store.append_accounts(&self.pending_accounts)?;
+            store.append_app_hash(restore.height, &restore.app_hash)?;
+            store.save_meta(restore.height, &restore.app_hash)?;
+        }
+        self.pending_accounts = vec![];
+        rsp(ApplySnapshotChunkResult::Accept, vec![], vec![])
+    }
+
+    // rebuild the tree from a snapshot's account set and check it lands on app_hash
+    fn apply_snapshot_entries(&mut self, height: u64, app_hash: Hash, entries: &[([u8; 32], Hash)]) -> Result<(), EasyFraudError> {
+        let mut root = None;
+        for (pubkey, leaf) in entries {
+            root = self.tree.insert(root.as_ref(), pubkey, leaf)
+                .map_err(|_| EasyFraudError::TreeInsertionError)?;
+        }
+        if root != Some(app_hash) {
+            return Err(EasyFraudError::InvalidSnapshot);
+        }
+        self.root = root;
+        self.volatile_root = root;
+        self.volatile_diffs = vec![];
+        self.height = height;
+        self.initialized = true;
+        self.app_hashes.insert(height, app_hash);
+        self.accounts = entries.iter().map(|(pubkey, _)| *pubkey).collect();
+        self.pending_accounts = entries.iter().map(|(pubkey, _)| *pubkey).collect();
+        Ok(())
+    }
+
+    // the account a block proposed by address pays its fees into
+    fn proposer_account(&self, address: &account::Id) -> Option<[u8; 32]> {
+        self.validators.get(address.as_bytes()).copied()
+    }
+
+    // tree nodes are content addressed and never overwritten, so every node under
+    // volatile_root is still there, whether it lives in memory or on disk.
+    // reverting is just pointing back at it, writing the old leaves back would
+    // only produce the same root again.
+    pub fn revert_volatile(&mut self) {
         self.root = self.volatile_root;
-        self.volatile_root = None;
         self.volatile_diffs = vec![];
+        for pubkey in self.pending_accounts.drain(..) {
+            self.accounts.remove(&pubkey);
+        }
     }
-}
\ No newline at end of file
+}
+
+// the genesis validators are in the tree too, so the app hash commits to them. they're
+// under a key nobody holds the private key of, and no transfer may pay into it.
+pub const VALIDATORS_KEY: [u8; 32] = *b"easyfraud/validators\0\0\0\0\0\0\0\0\0\0\0\0";
+
+fn validators_leaf(validators: &BTreeMap<[u8; 20], [u8; 32]>) -> Hash {
+    Sha256::digest(validators_to_bytes(validators)).into()
+}
+
+// a validator's address, the way CometBFT derives it from an ed25519 key
+pub fn validator_address(key: &[u8; 32]) -> [u8; 20] {
+    Sha256::digest(key)[..20].try_into().unwrap()
+}
+
+// apply_transfer for a transaction of any kind
+pub fn apply_tx(tree: &mut Monotree<TreeDB>, chain_id: &str, root: Option<&Hash>, fee_recipient: Option<&[u8; 32]>, tx: &Tx) -> Result<Transfer, EasyFraudError> {
+    match tx {
+        Tx::Transfer(stx) => apply_transfer(tree, chain_id, root, fee_recipient, stx),
+    }
+}
+
+// what a transfer did to the tree, with the leaves it overwrote
+pub struct Transfer {
+    pub txn: Transaction,
+    pub root: Option<Hash>,
+    pub old_sender_leaf: Hash,
+    pub old_recipient_leaf: Option<Hash>,
+    // the account the fee was paid into and its leaf from before, None if nothing was paid
+    pub fee_recipient: Option<([u8; 32], Option<Hash>)>,
+}
+
+// the transfer rules, on any tree and root. State runs them on its own tree,
+// fraud proofs run them on a tree made of nothing but the witnessed nodes.
+// root itself is never touched, a failed transfer leaves nothing to revert.
+// stx has to be signed for chain_id. the fee goes to fee_recipient, with no
+// recipient it's still taken from the sender and burned.
+pub fn apply_transfer(tree: &mut Monotree<TreeDB>, chain_id: &str, root: Option<&Hash>, fee_recipient: Option<&[u8; 32]>, stx: &SignedTransaction) -> Result<Transfer, EasyFraudError> {
+    let txn = stx.verify_and_deserialize(chain_id)?;
+    apply_verified_transfer(tree, root, fee_recipient, txn)
+}
+
+// apply_transfer once the signature is known to be good
+fn apply_verified_transfer(tree: &mut Monotree<TreeDB>, root: Option<&Hash>, fee_recipient: Option<&[u8; 32]>, txn: Transaction) -> Result<Transfer, EasyFraudError> {
+    // transaction must have > 0 satoshi
+    if txn.amount == 0 {
+        return Err(EasyFraudError::ZeroAmount);
+    }
+    // the recipient's balance is read before the sender's is debited,
+    // so paying yourself would mint the amount out of thin air
+    if txn.sender_pubkey == txn.recipient_pubkey {
+        return Err(EasyFraudError::SelfTransfer);
+    }
+    // the validators' leaf isn't an account, paying into it would overwrite them
+    if txn.recipient_pubkey == VALIDATORS_KEY {
+        return Err(EasyFraudError::ReservedAccount);
+    }
+
+    let old_sender_leaf: Hash = tree.get(root, &txn.sender_pubkey)
+        .map_err(|_| EasyFraudError::TreeGetError)?
+        .ok_or(EasyFraudError::SenderNotInitialized)?;
+    let old_sender_balance = leaf_to_num(&old_sender_leaf);
+    let old_sender_nonce = leaf_to_nonce(&old_sender_leaf);
+    // a signed transaction can only ever run once, the nonce it was signed
+    // with is behind the sender's as soon as it has
+    if txn.nonce != old_sender_nonce {
+        return Err(EasyFraudError::InvalidNonce { expected: old_sender_nonce, actual: txn.nonce });
+    }
+
+    let old_recipient_leaf: Option<Hash> = tree.get(root, &txn.recipient_pubkey)
+        .map_err(|_| EasyFraudError::TreeGetError)?;
+    // a new account starts out at nonce 0 and nothing
+    let recipient_leaf = old_recipient_leaf.unwrap_or([0; 32]);
+
+    // validate the transaction
+    let cost = txn.amount.checked_add(txn.fee)
+        .ok_or(EasyFraudError::InsufficientBalance)?;
+    if old_sender_balance <= cost {
+        return Err(EasyFraudError::InsufficientBalance)
+    }
+    let new_recipient_balance = leaf_to_num(&recipient_leaf).checked_add(txn.amount)
+        .ok_or(EasyFraudError::BalanceOverflow)?;
+
+    let new_sender_leaf = account_leaf(old_sender_nonce + 1, old_sender_balance - cost);
+    // receiving doesn't touch the recipient's nonce
+    let new_recipient_leaf = account_leaf(leaf_to_nonce(&recipient_leaf), new_recipient_balance);
+
+    let first_root = tree.insert(root, &txn.sender_pubkey, &new_sender_leaf)
+        .map_err(|_| EasyFraudError::TreeInsertionError)?;
+    let root = tree.insert(first_root.as_ref(), &txn.recipient_pubkey, &new_recipient_leaf)
+        .map_err(|_| EasyFraudError::TreeInsertionError)?;
+
+    // the fee is paid last, on top of what the transfer left behind,
+    // so it adds up when the proposer is the sender or the recipient
+    let (root, fee_recipient) = match fee_recipient {
+        Some(fee_recipient) if txn.fee > 0 => {
+            let old_leaf: Option<Hash> = tree.get(root.as_ref(), fee_recipient)
+                .map_err(|_| EasyFraudError::TreeGetError)?;
+            let leaf = old_leaf.unwrap_or([0; 32]);
+            let balance = leaf_to_num(&leaf).checked_add(txn.fee)
+                .ok_or(EasyFraudError::BalanceOverflow)?;
+            let root = tree.insert(root.as_ref(), fee_recipient, &account_leaf(leaf_to_nonce(&leaf), balance))
+                .map_err(|_| EasyFraudError::TreeInsertionError)?;
+            (root, Some((*fee_recipient, old_leaf)))
+        }
+        _ => (root, None),
+    };
+    Ok(Transfer {
+        txn,
+        root,
+        old_sender_leaf,
+        old_recipient_leaf,
+        fee_recipient,
+    })
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use monotree::Hash;
+
+use crate::errors::EasyFraudError;
+use crate::snapshot::{validators_from_bytes, validators_to_bytes};
+
+// bookkeeping that lives next to the persisted tree:
+//   <home>/meta        last committed height (u64 le) followed by its app hash
+//   <home>/accounts    every pubkey ever written to the tree, appended 32 bytes at a time
+//   <home>/app_hashes  (height u64 le, app hash) for every commit, appended
+//   <home>/da_height   next da height a derivation reads (u64 le)
+//   <home>/validators  (address 20, ed25519 key 32) of every genesis validator
+//   <home>/reported    height (u64 le) of every block a watchtower reported, appended
+// the tree is flushed first and meta is written last, so meta never points at
+// a root the tree doesn't have. anything appended past meta's height is ignored.
+pub struct BlockStore {
+    home: PathBuf,
+}
+
+impl BlockStore {
+    pub fn open(home: impl AsRef<Path>) -> Result<Self, EasyFraudError> {
+        let home = home.as_ref().to_path_buf();
+        fs::create_dir_all(&home)
+            .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+        Ok(BlockStore { home })
+    }
+
+    pub fn save_meta(&self, height: u64, app_hash: &Hash) -> Result<(), EasyFraudError> {
+        let mut buf = [0; 40];
+        buf[..8].copy_from_slice(&height.to_le_bytes());
+        buf[8..].copy_from_slice(&app_hash[..]);
+        write_atomic(&self.home.join("meta"), &buf)
+    }
+
+    // (last committed height, app hash), None if nothing was ever committed
+    pub fn load_meta(&self) -> Result<Option<(u64, Hash)>, EasyFraudError> {
+        let buf = match read_optional(&self.home.join("meta"))? {
+            Some(buf) => buf,
+            None => return Ok(None),
+        };
+        if buf.len() != 40 {
+            return Err(EasyFraudError::Storage("meta is corrupted".into()));
+        }
+        let height = u64::from_le_bytes(buf[..8].try_into().unwrap());
+        let app_hash: Hash = buf[8..].try_into().unwrap();
+        Ok(Some((height, app_hash)))
+    }
+
+    pub fn append_accounts(&self, accounts: &[[u8; 32]]) -> Result<(), EasyFraudError> {
+        append(&self.home.join("accounts"), &accounts.concat(), 32)
+    }
+
+    pub fn load_accounts(&self) -> Result<Vec<[u8; 32]>, EasyFraudError> {
+        let buf = read_optional(&self.home.join("accounts"))?.unwrap_or_default();
+        // a torn append can leave a partial record at the end, the next append cuts it off
+        Ok(buf.chunks_exact(32)
+            .map(|pubkey| pubkey.try_into().unwrap())
+            .collect())
+    }
+
+    pub fn append_app_hash(&self, height: u64, app_hash: &Hash) -> Result<(), EasyFraudError> {
+        append(&self.home.join("app_hashes"), &[&height.to_le_bytes()[..], &app_hash[..]].concat(), 40)
+    }
+
+    pub fn load_app_hashes(&self, up_to: u64) -> Result<BTreeMap<u64, Hash>, EasyFraudError> {
+        let buf = read_optional(&self.home.join("app_hashes"))?.unwrap_or_default();
+        Ok(buf.chunks_exact(40)
+            .map(|record| (u64::from_le_bytes(record[..8].try_into().unwrap()), record[8..].try_into().unwrap()))
+            .filter(|(height, _)| *height <= up_to)
+            .collect())
+    }
+
+    pub fn save_validators(&self, validators: &BTreeMap<[u8; 20], [u8; 32]>) -> Result<(), EasyFraudError> {
+        write_atomic(&self.home.join("validators"), &validators_to_bytes(validators))
+    }
+
+    pub fn load_validators(&self) -> Result<BTreeMap<[u8; 20], [u8; 32]>, EasyFraudError> {
+        let buf = read_optional(&self.home.join("validators"))?.unwrap_or_default();
+        validators_from_bytes(&buf)
+            .map_err(|_| EasyFraudError::Storage("validators is corrupted".into()))
+    }
+
+    pub fn append_reported(&self, height: u64) -> Result<(), EasyFraudError> {
+        append(&self.home.join("reported"), &height.to_le_bytes(), 8)
+    }
+
+    pub fn load_reported(&self) -> Result<BTreeSet<u64>, EasyFraudError> {
+        let buf = read_optional(&self.home.join("reported"))?.unwrap_or_default();
+        Ok(buf.chunks_exact(8)
+            .map(|height| u64::from_le_bytes(height.try_into().unwrap()))
+            .collect())
+    }
+
+    pub fn save_da_height(&self, da_height: u64) -> Result<(), EasyFraudError> {
+        write_atomic(&self.home.join("da_height"), &da_height.to_le_bytes())
+    }
+
+    pub fn load_da_height(&self) -> Result<Option<u64>, EasyFraudError> {
+        match read_optional(&self.home.join("da_height"))? {
+            Some(buf) => Ok(Some(u64::from_le_bytes(buf.try_into()
+                .map_err(|_| EasyFraudError::Storage("da_height is corrupted".into()))?))),
+            None => Ok(None),
+        }
+    }
+}
+
+// write to a temp file and rename over the target so a crash never leaves half a file
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), EasyFraudError> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+    fs::rename(&tmp, path)
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))
+}
+
+// append fixed size records, dropping a partial record a crash may have left behind
+fn append(path: &Path, data: &[u8], record_len: u64) -> Result<(), EasyFraudError> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+    let len = file.metadata()
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))?
+        .len();
+    if len % record_len != 0 {
+        file.set_len(len - len % record_len)
+            .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+    }
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| EasyFraudError::Storage(e.to_string()))
+}
+
+pub fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, EasyFraudError> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(EasyFraudError::Storage(e.to_string())),
+    }
+}
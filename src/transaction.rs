@@ -1,33 +1,72 @@
 use ed25519_dalek::{
+    verify_batch,
     VerifyingKey,
     SigningKey,
     Signature,
     Signer,
     Verifier,
 };
+use rayon::prelude::*;
 
 use crate::errors::EasyFraudError;
 
-// total size: 72 bytes
+// sender 32 | recipient 32 | amount u64 le | nonce u64 le | fee u64 le
+pub const TRANSACTION_SIZE: usize = 88;
+// transaction | signature 64
+pub const SIGNED_TRANSACTION_SIZE: usize = TRANSACTION_SIZE + 64;
+// before nonces a transaction ended at the amount, before fees at the nonce, and before
+// envelopes it went on the wire as is. those are rejected rather than guessed at, a
+// transaction without a nonce could be replayed forever and one without a fee doesn't
+// say what it's willing to pay.
+const LEGACY_SIGNED_TRANSACTION_SIZES: [usize; 2] = [136, 144];
+
+// every transaction goes on the wire in an envelope: version u8 | kind u8 |
+// payload length u32 le | payload. the length is readable whatever the version or
+// kind, so a transaction this node doesn't know can still be skipped over.
+const ENVELOPE_VERSION: u8 = 1;
+const ENVELOPE_HEADER: usize = 6;
+// kinds, the payload of each is
+//   TRANSFER   a SignedTransaction
+const TRANSFER: u8 = 0;
+
+// what a signature is over: the domain, the scheme version, the chain id's length
+// (u32 le) and the chain id, then the transaction. a transaction signed for one
+// chain doesn't verify on a fork or testnet the same keys are used on.
+const SIGNING_DOMAIN: &[u8] = b"easyfraud/tx";
+const SIGNING_SCHEME: u8 = 1;
+
+// signatures checked together in one batch. a batch with a bad signature in it is
+// checked again one by one, so this is also how much one bad signature costs.
+const VERIFY_BATCH_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub struct Transaction {
     pub sender_pubkey: [u8; 32],
     pub recipient_pubkey: [u8; 32],
     pub amount: u64,
+    // the sender's nonce in the tree this has to match, it goes up by one per transfer sent
+    pub nonce: u64,
+    // paid by the sender on top of amount, to the proposer of the block it lands in
+    pub fee: u64,
 }
 
-// total size: 136 bytes
 #[derive(Debug)]
 pub struct SignedTransaction {
-    pub transaction_data: [u8; 72],
+    pub transaction_data: [u8; TRANSACTION_SIZE],
     pub signature: [u8; 64],
 }
 
+// a transaction of any kind, the way it comes out of its envelope
+#[derive(Debug)]
+pub enum Tx {
+    Transfer(SignedTransaction),
+}
+
 impl Transaction {
 
-    pub fn sign(&self, signing_key: &SigningKey) -> SignedTransaction {
+    pub fn sign(&self, chain_id: &str, signing_key: &SigningKey) -> SignedTransaction {
         let serialized = self.serialize();
-        let signature = signing_key.sign(&serialized[..]);
+        let signature = signing_key.sign(&signing_message(chain_id, &serialized));
         SignedTransaction { 
             transaction_data: serialized, 
             signature: signature.to_bytes(), 
@@ -35,59 +74,157 @@ impl Transaction {
     }
 
     // we're rolling our own share-aware serialization!
-    pub fn serialize(&self) -> [u8; 72] {
-        let mut buf = [0u8; 72];
+    pub fn serialize(&self) -> [u8; TRANSACTION_SIZE] {
+        let mut buf = [0u8; TRANSACTION_SIZE];
         buf[..32].copy_from_slice(&self.sender_pubkey[..]);
         buf[32..64].copy_from_slice(&self.recipient_pubkey[..]);
         buf[64..72].copy_from_slice(&self.amount.to_le_bytes()[..]);
+        buf[72..80].copy_from_slice(&self.nonce.to_le_bytes()[..]);
+        buf[80..88].copy_from_slice(&self.fee.to_le_bytes()[..]);
         buf
     }
 
-    pub fn deserialize(bytes: [u8; 72]) -> Result<Self, EasyFraudError> {
+    pub fn deserialize(bytes: [u8; TRANSACTION_SIZE]) -> Result<Self, EasyFraudError> {
         Ok(Transaction {
             sender_pubkey: bytes[..32].try_into()
                 .map_err(|_| EasyFraudError::TransactionDeserializationError)?,
             recipient_pubkey: bytes[32..64].try_into()
                 .map_err(|_| EasyFraudError::TransactionDeserializationError)?,
-            amount: u64::from_le_bytes(bytes[64..].try_into().map_err(|_| EasyFraudError::TransactionDeserializationError)?),
-
+            amount: u64::from_le_bytes(bytes[64..72].try_into().map_err(|_| EasyFraudError::TransactionDeserializationError)?),
+            nonce: u64::from_le_bytes(bytes[72..80].try_into().map_err(|_| EasyFraudError::TransactionDeserializationError)?),
+            fee: u64::from_le_bytes(bytes[80..88].try_into().map_err(|_| EasyFraudError::TransactionDeserializationError)?),
         })
     }
 }
 
 impl SignedTransaction {
-    pub fn serialize(&self) -> [u8; 136] {
-        let mut buf = [0u8; 136];
-        buf[..72].copy_from_slice(&self.transaction_data[..]);
-        buf[72..].copy_from_slice(&self.signature[..]);
+    pub fn serialize(&self) -> [u8; SIGNED_TRANSACTION_SIZE] {
+        let mut buf = [0u8; SIGNED_TRANSACTION_SIZE];
+        buf[..TRANSACTION_SIZE].copy_from_slice(&self.transaction_data[..]);
+        buf[TRANSACTION_SIZE..].copy_from_slice(&self.signature[..]);
         buf
     }
 
-    pub fn deserialize(bytes: [u8; 136]) -> Result<Self, EasyFraudError> {
+    pub fn deserialize(bytes: [u8; SIGNED_TRANSACTION_SIZE]) -> Result<Self, EasyFraudError> {
         Ok(SignedTransaction { 
-            transaction_data: bytes[..72].try_into()
+            transaction_data: bytes[..TRANSACTION_SIZE].try_into()
                 .map_err(|_| EasyFraudError::TransactionDeserializationError)?,
-            signature: bytes[72..].try_into()
+            signature: bytes[TRANSACTION_SIZE..].try_into()
                 .map_err(|_| EasyFraudError::TransactionDeserializationError)?,
         })
     }
 
-    pub fn verify(&self) -> Result<bool, EasyFraudError> {
+    pub fn verify(&self, chain_id: &str) -> Result<bool, EasyFraudError> {
         let txn = Transaction::deserialize(self.transaction_data)?;
         let vk = VerifyingKey::from_bytes(&txn.sender_pubkey)
             .map_err(|_| EasyFraudError::TransactionDeserializationError)?;
         let sig = Signature::from_bytes(&self.signature);
-        Ok(vk.verify(&self.transaction_data, &sig).is_ok())
+        Ok(vk.verify(&signing_message(chain_id, &self.transaction_data), &sig).is_ok())
     }
 
-    pub fn verify_and_deserialize(&self) -> Result<Transaction, EasyFraudError> {
+    pub fn to_envelope(&self) -> Vec<u8> {
+        envelope(TRANSFER, &self.serialize())
+    }
+
+    // a signature made for another chain, or another scheme, is just an invalid one
+    pub fn verify_and_deserialize(&self, chain_id: &str) -> Result<Transaction, EasyFraudError> {
         let txn = Transaction::deserialize(self.transaction_data)?;
         let vk = VerifyingKey::from_bytes(&txn.sender_pubkey)
             .map_err(|_| EasyFraudError::TransactionDeserializationError)?;
         let sig = Signature::from_bytes(&self.signature);
-        if vk.verify(&self.transaction_data, &sig).is_ok() {
+        if vk.verify(&signing_message(chain_id, &self.transaction_data), &sig).is_ok() {
             return Ok(txn);
         }
         Err(EasyFraudError::InvalidSignature)
     }
+}
+
+impl Tx {
+    // the part of the transaction its sender signed
+    pub fn signed(&self) -> &SignedTransaction {
+        match self {
+            Tx::Transfer(stx) => stx,
+        }
+    }
+
+    // exactly one envelope, anything off about it is the transaction's fault.
+    // a legacy one gets its own error so whoever sent it knows to sign it again.
+    pub fn decode(bytes: &[u8]) -> Result<Self, EasyFraudError> {
+        if bytes.first() != Some(&ENVELOPE_VERSION) && LEGACY_SIGNED_TRANSACTION_SIZES.contains(&bytes.len()) {
+            return Err(EasyFraudError::LegacyTransaction);
+        }
+        if envelope_len(bytes)? != bytes.len() {
+            return Err(EasyFraudError::InvalidEnvelope);
+        }
+        if bytes[0] != ENVELOPE_VERSION {
+            return Err(EasyFraudError::UnsupportedEnvelopeVersion(bytes[0]));
+        }
+        let payload = &bytes[ENVELOPE_HEADER..];
+        match bytes[1] {
+            TRANSFER if LEGACY_SIGNED_TRANSACTION_SIZES.contains(&payload.len()) => Err(EasyFraudError::LegacyTransaction),
+            TRANSFER => Ok(Tx::Transfer(SignedTransaction::deserialize(payload.try_into()
+                .map_err(|_| EasyFraudError::TransactionDeserializationError)?)?)),
+            kind => Err(EasyFraudError::UnknownTransactionKind(kind)),
+        }
+    }
+}
+
+fn envelope(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(ENVELOPE_HEADER + payload.len());
+    buf.push(ENVELOPE_VERSION);
+    buf.push(kind);
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+// how long the envelope bytes start with is, header included
+pub fn envelope_len(bytes: &[u8]) -> Result<usize, EasyFraudError> {
+    let len = bytes.get(2..ENVELOPE_HEADER)
+        .ok_or(EasyFraudError::InvalidEnvelope)?;
+    Ok(ENVELOPE_HEADER + u32::from_le_bytes(len.try_into().unwrap()) as usize)
+}
+
+// whether each signature verifies, the batches are spread over every core.
+// the batch equation isn't guaranteed to agree with a single verify on crafted
+// signatures, so only building a proposal uses this, on what already passed verify
+// in CheckTx. ProcessProposal, FinalizeBlock and fraud proofs verify one by one.
+// moving everything to verify_strict would let those batch too, but it turns away
+// signatures verify takes, and so transactions that are valid today.
+pub fn verify_signatures(chain_id: &str, stxs: &[&SignedTransaction]) -> Vec<bool> {
+    stxs.par_chunks(VERIFY_BATCH_SIZE)
+        .flat_map_iter(|batch| verify_signature_batch(chain_id, batch))
+        .collect()
+}
+
+fn verify_signature_batch(chain_id: &str, batch: &[&SignedTransaction]) -> Vec<bool> {
+    let keys = batch.iter()
+        .map(|stx| VerifyingKey::from_bytes(stx.transaction_data[..32].try_into().unwrap()))
+        .collect::<Result<Vec<VerifyingKey>, _>>();
+    if let Ok(keys) = keys {
+        let messages = batch.iter()
+            .map(|stx| signing_message(chain_id, &stx.transaction_data))
+            .collect::<Vec<Vec<u8>>>();
+        let messages = messages.iter().map(Vec::as_slice).collect::<Vec<&[u8]>>();
+        let signatures = batch.iter()
+            .map(|stx| Signature::from_bytes(&stx.signature))
+            .collect::<Vec<Signature>>();
+        if verify_batch(&messages, &signatures, &keys).is_ok() {
+            return vec![true; batch.len()];
+        }
+    }
+    // something in here is bad, find out what
+    batch.iter()
+        .map(|stx| matches!(stx.verify(chain_id), Ok(true)))
+        .collect()
+}
+
+fn signing_message(chain_id: &str, transaction_data: &[u8; TRANSACTION_SIZE]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNING_DOMAIN.len() + 5 + chain_id.len() + TRANSACTION_SIZE);
+    message.extend_from_slice(SIGNING_DOMAIN);
+    message.push(SIGNING_SCHEME);
+    message.extend_from_slice(&(chain_id.len() as u32).to_le_bytes());
+    message.extend_from_slice(chain_id.as_bytes());
+    message.extend_from_slice(transaction_data);
+    message
 }
\ No newline at end of file
@@ -1,5 +1,116 @@
+use monotree::{
+    hasher::Blake3,
+    Hash,
+    Hasher,
+    Proof,
+};
+
+use crate::errors::EasyFraudError;
+
+// account leaves: 16 zero bytes | nonce u64 le | balance u64 le.
+// leaves from before nonces were zero up to the balance, so they read as nonce 0.
 pub fn leaf_to_num(leaf: &[u8; 32]) -> u64 {
     let mut buf = [0u8; 8];
     buf.copy_from_slice(&leaf[24..32]);
     u64::from_le_bytes(buf)
-}
\ No newline at end of file
+}
+
+pub fn leaf_to_nonce(leaf: &[u8; 32]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&leaf[16..24]);
+    u64::from_le_bytes(buf)
+}
+
+pub fn account_leaf(nonce: u64, balance: u64) -> [u8; 32] {
+    let mut leaf = [0u8; 32];
+    leaf[16..24].copy_from_slice(&nonce.to_le_bytes());
+    leaf[24..32].copy_from_slice(&balance.to_le_bytes());
+    leaf
+}
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>, EasyFraudError> {
+    if s.len() % 2 != 0 {
+        return Err(EasyFraudError::InvalidHex);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2)
+            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            .ok_or(EasyFraudError::InvalidHex))
+        .collect()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// standard alphabet with padding, how celestia-node puts bytes in json
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+pub fn from_base64(s: &str) -> Result<Vec<u8>, EasyFraudError> {
+    if s.len() % 4 != 0 {
+        return Err(EasyFraudError::InvalidBase64);
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, chunk) in s.as_bytes().chunks(4).enumerate() {
+        let last = i == s.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(EasyFraudError::InvalidBase64);
+        }
+        let mut n = 0u32;
+        for c in &chunk[..4 - padding] {
+            let digit = BASE64.iter().position(|b| b == c)
+                .ok_or(EasyFraudError::InvalidBase64)?;
+            n = (n << 6) | digit as u32;
+        }
+        n <<= 6 * padding;
+        bytes.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(bytes)
+}
+
+// monotree proofs are a list of (is right child, sibling bytes).
+// on the wire each step is one direction byte, a u16 le length and the bytes.
+pub fn proof_to_bytes(proof: &Proof) -> Vec<u8> {
+    let mut buf = vec![];
+    for (right, cut) in proof {
+        buf.push(*right as u8);
+        buf.extend_from_slice(&(cut.len() as u16).to_le_bytes());
+        buf.extend_from_slice(cut);
+    }
+    buf
+}
+
+pub fn proof_from_bytes(mut data: &[u8]) -> Result<Proof, EasyFraudError> {
+    let mut proof = vec![];
+    while !data.is_empty() {
+        if data.len() < 3 || data[0] > 1 {
+            return Err(EasyFraudError::InvalidProof);
+        }
+        let len = u16::from_le_bytes([data[1], data[2]]) as usize;
+        let cut = data.get(3..3 + len).ok_or(EasyFraudError::InvalidProof)?;
+        proof.push((data[0] == 1, cut.to_vec()));
+        data = &data[3 + len..];
+    }
+    Ok(proof)
+}
+
+// check a monotree inclusion proof for leaf under root, without a tree
+pub fn verify_leaf_proof(root: &Hash, leaf: &Hash, proof: &Proof) -> bool {
+    monotree::verify_proof(&Blake3::new(), Some(root), leaf, Some(proof))
+}
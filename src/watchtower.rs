@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use celestia_types::Blob;
+use log::{info, warn};
+use serde_json::{json, Value};
+
+use crate::blob::blob_shares;
+use crate::block::OutgoingBlock;
+use crate::config::Config;
+use crate::derive::{self, Derivation, POLL_INTERVAL};
+use crate::errors::EasyFraudError;
+use crate::fraud::{verify_fraud_proof, FraudProof, FraudReport, FraudVerdict};
+use crate::http;
+use crate::state::State;
+use crate::store::write_atomic;
+use crate::utils::to_base64;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// where fraud reports go
+pub enum Sink {
+    // <dir>/<height>, the serialized report for the block at height
+    File(PathBuf),
+    // POSTed as application/octet-stream to an http:// url
+    Http(String, String),
+    // broadcast_tx_sync through a CometBFT node's rpc. whatever chain that is has to
+    // know what to do with a report, easyfraud's own CheckTx doesn't take them.
+    Rollup(String, String),
+}
+
+impl Sink {
+    // file:<dir> | http://host:port/path | rollup:http://host:port
+    pub fn open(spec: &str) -> Result<Self, EasyFraudError> {
+        let invalid = || EasyFraudError::InvalidArgument("--watch".into());
+        match spec.split_once(':') {
+            Some(("file", dir)) => {
+                fs::create_dir_all(dir)
+                    .map_err(|e| EasyFraudError::Storage(e.to_string()))?;
+                Ok(Sink::File(dir.into()))
+            }
+            Some(("http", _)) => {
+                let (address, path) = http::split_url(spec).ok_or_else(invalid)?;
+                Ok(Sink::Http(address, path))
+            }
+            Some(("rollup", url)) => {
+                let (address, path) = http::split_url(url).ok_or_else(invalid)?;
+                Ok(Sink::Rollup(address, path))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn submit(&self, report: &FraudReport) -> Result<(), EasyFraudError> {
+        let bytes = report.serialize();
+        match self {
+            Sink::File(dir) => write_atomic(&dir.join(report.height.to_string()), &bytes),
+            Sink::Http(address, path) => {
+                http::post(address, path, "application/octet-stream", None, &bytes, TIMEOUT)?;
+                Ok(())
+            }
+            Sink::Rollup(address, path) => {
+                let body = json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "broadcast_tx_sync",
+                    "params": { "tx": to_base64(&bytes) },
+                }).to_string();
+                let rsp = http::post(address, path, "application/json", None, body.as_bytes(), TIMEOUT)?;
+                let rsp: Value = serde_json::from_slice(&rsp)
+                    .map_err(|e| EasyFraudError::Http(e.to_string()))?;
+                if let Some(error) = rsp.get("error") {
+                    return Err(EasyFraudError::ReportRejected(error.to_string()));
+                }
+                match rsp["result"]["code"].as_u64() {
+                    Some(0) => Ok(()),
+                    _ => Err(EasyFraudError::ReportRejected(rsp["result"]["log"].as_str()
+                        .unwrap_or_default()
+                        .into())),
+                }
+            }
+        }
+    }
+}
+
+// follows the da layer like a derivation, and when a block's pair doesn't check out
+// proves it and hands the report to sink. the fraudulent block is skipped like a
+// derivation skips it and the watchtower keeps going. the heights it reported are
+// persisted next to the derivation's progress, so a block it reads again after a
+// restart isn't reported twice.
+pub struct Watchtower {
+    pub derivation: Derivation,
+    pub sink: Sink,
+    // heights of the blocks reported so far
+    pub reported: BTreeSet<u64>,
+}
+
+impl Watchtower {
+    pub fn new(derivation: Derivation, sink: Sink) -> Result<Self, EasyFraudError> {
+        let reported = match &derivation.state.store {
+            Some(store) => store.load_reported()?,
+            None => BTreeSet::new(),
+        };
+        Ok(Watchtower {
+            derivation,
+            sink,
+            reported,
+        })
+    }
+
+    // Derivation::step, with a fraudulent block reported the first time it comes up.
+    // a report that fails ends the step, it's tried again at the same da height.
+    pub fn step(&mut self) -> Result<bool, EasyFraudError> {
+        let (sink, reported) = (&self.sink, &mut self.reported);
+        self.derivation.step_with(|derivation, blob, height, block, e| match e {
+            EasyFraudError::FraudulentBlock { index, .. } if !reported.contains(&height) => {
+                report(derivation, sink, blob, height, block, index)?;
+                if let Some(store) = &derivation.state.store {
+                    store.append_reported(height)?;
+                }
+                reported.insert(height);
+                warn!("reported fraud in block {} at pair {}", height, index);
+                Ok(())
+            }
+            e => {
+                warn!("skipped block {} at da height {}: {}", height, derivation.da_height, e);
+                Ok(())
+            }
+        })
+    }
+
+    pub fn sync(&mut self) -> Result<u64, EasyFraudError> {
+        while self.step()? {}
+        Ok(self.derivation.state.height)
+    }
+}
+
+// prove the pair at index of the block blob holds is fraudulent, with the blob's shares
+// from the da height the derivation is at. the proof is checked the way whoever gets the
+// report checks it, one that doesn't hold up isn't sent.
+fn report(derivation: &mut Derivation, sink: &Sink, blob: &Blob, height: u64, block: &OutgoingBlock, index: usize) -> Result<(), EasyFraudError> {
+    let (namespace, da_height) = (derivation.state.namespace, derivation.da_height);
+    let da = &derivation.da;
+    let prove = |share: usize| da.get_proof(da_height, namespace, &blob.commitment, share);
+    let proof = FraudProof::generate(&mut derivation.state, block, index, &blob_shares(blob), prove)?;
+    // the block didn't apply, state is still where it started from
+    let start_root = derivation.state.root.ok_or(EasyFraudError::NullApphash)?;
+    let data_root = da.roots(da_height)?.data_root();
+    let verdict = verify_fraud_proof(&proof.serialize(), &derivation.state.chain_id, namespace, &derivation.sequencer, &data_root, height, &start_root)?;
+    if verdict == FraudVerdict::Honest {
+        return Err(EasyFraudError::InvalidFraudProof);
+    }
+    sink.submit(&FraudReport {
+        height,
+        da_height,
+        proof,
+    })
+}
+
+// the --watch mode, follow the da layer until stop is set, reporting fraud along the way
+pub fn run(state: State, config: Config, stop: &AtomicBool) -> Result<(), EasyFraudError> {
+    let spec = config.watch.as_ref()
+        .ok_or(EasyFraudError::InvalidArgument("--watch".into()))?;
+    let mut watchtower = Watchtower::new(derive::open(state, &config)?, Sink::open(spec)?)?;
+    while !stop.load(Ordering::Relaxed) {
+        let before = watchtower.derivation.state.height;
+        match watchtower.sync() {
+            Ok(height) if height != before => {
+                info!("checked height {}, next da height {}", height, watchtower.derivation.da_height);
+            }
+            Ok(_) => {}
+            // a sink that's down shouldn't stop the watching, the report goes out once it's back
+            Err(e) => warn!("could not check da height {}: {}", watchtower.derivation.da_height, e),
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}